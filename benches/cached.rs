@@ -0,0 +1,36 @@
+// A long expression that repeats the same costly subexpressions: eval() computes every copy of them,
+// eval_cached() computes each of them once. There is no Cargo manifest, so there is no criterion either.
+// Build it with optimizations and run it:
+//     rustc -O --edition 2021 benches/cached.rs -o cached && ./cached
+#[path = "../shunting_yard.rs"]
+#[allow(dead_code)]
+mod shunting_yard;
+
+use std::time::Instant;
+
+const RUNS: u32 = 20;
+
+fn main() {
+    // Two consecutive Fibonacci numbers are the slowest case for the Euclidean algorithm.
+    let term = "170! / 169! - gcd(8944394323791464, 5527939700884757) * lcm(5527939700884757, 3416454622906707)";
+    let input = vec![term; 200].join(" + ");
+    let tree = shunting_yard::parse_str(&input).unwrap();
+
+    let start = Instant::now();
+    let mut plain = 0.0;
+    for _ in 0..RUNS {
+        plain += shunting_yard::eval(&tree);
+    }
+    let evaluating = start.elapsed();
+
+    let start = Instant::now();
+    let mut cached = 0.0;
+    for _ in 0..RUNS {
+        cached += shunting_yard::eval_cached(&tree);
+    }
+    let caching = start.elapsed();
+
+    println!("eval: {:?} for {} runs", evaluating, RUNS);
+    println!("eval_cached: {:?} for {} runs", caching, RUNS);
+    println!("the same sum: {}", plain == cached);
+}
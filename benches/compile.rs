@@ -0,0 +1,38 @@
+// Evaluating one expression for many values of x: scanning and parsing it every time
+// against compiling it once and running the program. There is no Cargo manifest, so there is
+// no criterion either. Build it with optimizations and run it:
+//     rustc -O --edition 2021 benches/compile.rs -o compile && ./compile
+#[path = "../shunting_yard.rs"]
+#[allow(dead_code)]
+mod shunting_yard;
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+const RUNS: usize = 100000;
+
+fn main() {
+    let input = "3 * x ^ 2 - 2 * x + sqrt(x + 1) / (1 + x % 7) + max(x, 10, 20)";
+    let mut bindings = HashMap::new();
+
+    let start = Instant::now();
+    let mut parsed = 0.0;
+    for i in 0..RUNS {
+        bindings.insert(String::from("x"), i as f64);
+        parsed += shunting_yard::evaluate_with(input, &bindings).unwrap();
+    }
+    let parsing = start.elapsed();
+
+    let start = Instant::now();
+    let program = shunting_yard::compile(input).unwrap();
+    let mut compiled = 0.0;
+    for i in 0..RUNS {
+        bindings.insert(String::from("x"), i as f64);
+        compiled += program.run(&bindings).unwrap();
+    }
+    let running = start.elapsed();
+
+    println!("evaluate_with: {:?} for {} runs", parsing, RUNS);
+    println!("compile once, run: {:?} for {} runs", running, RUNS);
+    println!("the same sum: {}", parsed == compiled);
+}
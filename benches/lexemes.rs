@@ -0,0 +1,27 @@
+// Scanning a long expression with long literals, where building the lexemes used to dominate.
+// There is no Cargo manifest, so there is no criterion either. Build it with optimizations and run it:
+//     rustc -O --edition 2021 benches/lexemes.rs -o lexemes && ./lexemes
+#[path = "../shunting_yard.rs"]
+#[allow(dead_code)]
+mod shunting_yard;
+
+use std::time::Instant;
+
+const RUNS: u32 = 20;
+
+fn main() {
+    let term = "123456789.123456789 * (987654321 - 0.000001) / 3";
+    let input = vec![term; 5000].join(" + ");
+
+    let start = Instant::now();
+    let mut tokens = 0;
+    for _ in 0..RUNS {
+        tokens += shunting_yard::tokenize(&input).unwrap().len();
+    }
+    let elapsed = start.elapsed();
+
+    println!("{} bytes, {} tokens", input.len(), tokens / RUNS as usize);
+    println!("tokenize: {:?} per run", elapsed / RUNS);
+    // The same result every time shows the lexemes are still the same numbers.
+    println!("result: {}", shunting_yard::evaluate_str(&input).unwrap());
+}
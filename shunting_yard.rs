@@ -1,263 +1,6333 @@
-use std::fs;
+// Built for the browser, the file is a library, and the command line part of it goes unused.
+// Without the standard library there is no command line at all, and nowhere to print the trace to.
+#![cfg_attr(any(feature = "wasm", feature = "no_std"), allow(dead_code))]
+// The rest needs only alloc, see the README.
+#![cfg_attr(feature = "no_std", no_std)]
+
+#[cfg(feature = "no_std")]
+#[macro_use]
+extern crate alloc;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+#[cfg(all(test, feature = "wasm"))]
+extern crate wasm_bindgen_test;
+#[cfg(feature = "rand")]
+extern crate rand;
+
+#[cfg(not(feature = "no_std"))]
+use std::{collections::HashMap, env, error, f64::consts, fmt, fs, io, io::BufRead, io::Read, io::Write, mem, num::IntErrorKind, ops::Range, process};
+
+// alloc has no HashMap: its hasher is seeded by the OS. The ordered map does just as well for a few names.
+#[cfg(feature = "no_std")]
+use alloc::{boxed::Box, collections::BTreeMap as HashMap, string::{String, ToString}, vec::Vec};
+#[cfg(feature = "no_std")]
+use core::{error, f64::consts, fmt, mem, num::IntErrorKind, ops::Range};
+#[cfg(feature = "no_std")]
+use float::Float;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TokenType {
+    Function,
+    Bang,
+    PercentOf,
+    Caret,
+    UMinus,
+    UPlus,
+    Star,
+    Slash,
+    DoubleSlash,
+    Percent,
+    Plus,
+    Minus,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    EqualEqual,
+    BangEqual,
+
+    Number,
+    Identifier,
+    LeftParen,
+    RightParen,
+    LeftBracket,
+    RightBracket,
+    LeftBrace,
+    RightBrace,
+    // The absolute value: |x - 1| is abs(x - 1).
+    LeftBar,
+    RightBar,
+    Comma,
+    Question,
+    Colon,
+    // Only in an assignment: x = 1 + 2.
+    Equal,
+
+    // A binary operator registered in an OperatorRegistry.
+    Custom(char),
+}
+
+impl TokenType {
+    // We can utilize zero precedence to distinguish operators from other token types.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            TokenType::Function => 7,
+            TokenType::Bang | TokenType::PercentOf => 6,
+            TokenType::Caret => 5,
+            TokenType::UMinus | TokenType::UPlus => 4,
+            TokenType::Star | TokenType::Slash | TokenType::DoubleSlash | TokenType::Percent => 3,
+            TokenType::Plus | TokenType::Minus => 2,
+            TokenType::Less | TokenType::LessEqual
+                | TokenType::Greater | TokenType::GreaterEqual
+                | TokenType::EqualEqual | TokenType::BangEqual => 1,
+            TokenType::Number | TokenType::Identifier => 0,
+            TokenType::LeftParen | TokenType::RightParen => 0,
+            TokenType::LeftBracket | TokenType::RightBracket => 0,
+            TokenType::LeftBrace | TokenType::RightBrace => 0,
+            TokenType::LeftBar | TokenType::RightBar => 0,
+            TokenType::Comma => 0,
+            // The conditional is handled on its own. It is below everything else anyway.
+            TokenType::Question | TokenType::Colon => 0,
+            TokenType::Equal => 0,
+            // The actual one is in the table of the registry.
+            TokenType::Custom(_) => 3,
+        }
+    }
+
+    // The exponentiation is right-associative: 2^3^2 is 2^(3^2). Everything else groups left to right.
+    pub fn associativity(&self) -> Associativity {
+        match self {
+            TokenType::Caret => Associativity::Right,
+            _ => Associativity::Left,
+        }
+    }
+
+    // Square brackets and braces group an expression the same way parentheses do.
+    fn is_opening(&self) -> bool {
+        *self == TokenType::LeftParen || *self == TokenType::LeftBracket || *self == TokenType::LeftBrace || *self == TokenType::LeftBar
+    }
+
+    fn is_closing(&self) -> bool {
+        *self == TokenType::RightParen || *self == TokenType::RightBracket || *self == TokenType::RightBrace || *self == TokenType::RightBar
+    }
+
+    // But a group must be closed by the same kind of a bracket.
+    fn closes(&self, opening: TokenType) -> bool {
+        match self {
+            TokenType::RightParen => opening == TokenType::LeftParen,
+            TokenType::RightBracket => opening == TokenType::LeftBracket,
+            TokenType::RightBrace => opening == TokenType::LeftBrace,
+            TokenType::RightBar => opening == TokenType::LeftBar,
+            _ => false,
+        }
+    }
+
+    fn is_unary(&self) -> bool {
+        self.is_postfix() || *self == TokenType::UMinus || *self == TokenType::UPlus
+    }
+
+    // Postfix operators follow their operand: 5! or 50%.
+    fn is_postfix(&self) -> bool {
+        *self == TokenType::Bang || *self == TokenType::PercentOf
+    }
+
+    // The operators that fail on a zero divisor.
+    fn is_division(&self) -> bool {
+        *self == TokenType::Slash || *self == TokenType::DoubleSlash || *self == TokenType::Percent
+    }
+
+    // Prefix operators have no left operand.
+    fn is_prefix(&self) -> bool {
+        *self == TokenType::UMinus || *self == TokenType::UPlus || *self == TokenType::Function
+    }
+
+    // How an operator of an expression tree is written down.
+    fn symbol(&self) -> String {
+        let symbol = match self {
+            TokenType::Bang => "!",
+            TokenType::Caret => "^",
+            TokenType::UMinus | TokenType::Minus => "-",
+            TokenType::UPlus | TokenType::Plus => "+",
+            TokenType::Star => "*",
+            TokenType::Slash => "/",
+            TokenType::DoubleSlash => "//",
+            TokenType::Percent | TokenType::PercentOf => "%",
+            TokenType::Less => "<",
+            TokenType::LessEqual => "<=",
+            TokenType::Greater => ">",
+            TokenType::GreaterEqual => ">=",
+            TokenType::EqualEqual => "==",
+            TokenType::BangEqual => "!=",
+            TokenType::Custom(symbol) => return symbol.to_string(),
+            _ => unreachable!("Invalid token type {:?} when an operator expected.", self),
+        };
+
+        symbol.to_string()
+    }
+
+    fn from_symbol(symbol: &str, unary: bool) -> Option<TokenType> {
+        let ttype = match (symbol, unary) {
+            ("-", true) => TokenType::UMinus,
+            ("+", true) => TokenType::UPlus,
+            ("!", true) => TokenType::Bang,
+            ("%", true) => TokenType::PercentOf,
+            ("^", false) => TokenType::Caret,
+            ("-", false) => TokenType::Minus,
+            ("+", false) => TokenType::Plus,
+            ("*", false) => TokenType::Star,
+            ("/", false) => TokenType::Slash,
+            ("//", false) => TokenType::DoubleSlash,
+            ("%", false) => TokenType::Percent,
+            ("<", false) => TokenType::Less,
+            ("<=", false) => TokenType::LessEqual,
+            (">", false) => TokenType::Greater,
+            (">=", false) => TokenType::GreaterEqual,
+            ("==", false) => TokenType::EqualEqual,
+            ("!=", false) => TokenType::BangEqual,
+            _ => return None,
+        };
+
+        Some(ttype)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Associativity {
+    Left,
+    Right,
+}
+
+// How tightly the operators bind in the shunting yard. By default it is TokenType::precedence()
+// and TokenType::associativity(). A dialect may change it, e.g. make `+` bind tighter than `*`.
+// Zero is reserved for the tokens that are not operators, so an operator should stay above it.
+#[derive(Debug, Clone)]
+pub struct PrecedenceTable {
+    operators: HashMap<TokenType, (u8, Associativity)>,
+}
+
+impl PrecedenceTable {
+
+    pub fn new() -> Self {
+        let mut table = PrecedenceTable { operators: HashMap::new() };
+
+        let operators = [
+            TokenType::Function, TokenType::Bang, TokenType::PercentOf, TokenType::Caret, TokenType::UMinus, TokenType::UPlus,
+            TokenType::Star, TokenType::Slash, TokenType::DoubleSlash, TokenType::Percent, TokenType::Plus, TokenType::Minus,
+            TokenType::Less, TokenType::LessEqual, TokenType::Greater, TokenType::GreaterEqual,
+            TokenType::EqualEqual, TokenType::BangEqual,
+        ];
+        for operator in operators.iter() {
+            table.set(*operator, operator.precedence(), operator.associativity());
+        }
+
+        table
+    }
+
+    // A cheap calculator applies the operators in the order they come: 2 + 3 * 4 is 20.
+    // Only the binary operators are flattened. The unary ones and the functions still bind tighter: 2 * -3! is -12.
+    pub fn calculator() -> Self {
+        let mut table = PrecedenceTable::new();
+
+        let operators = [
+            TokenType::Caret, TokenType::Star, TokenType::Slash, TokenType::DoubleSlash, TokenType::Percent,
+            TokenType::Plus, TokenType::Minus,
+            TokenType::Less, TokenType::LessEqual, TokenType::Greater, TokenType::GreaterEqual,
+            TokenType::EqualEqual, TokenType::BangEqual,
+        ];
+        for operator in operators.iter() {
+            table.set(*operator, 1, Associativity::Left);
+        }
+
+        table
+    }
+
+    // The table the settings ask for.
+    pub fn with_settings(settings: &Settings) -> Self {
+        let mut table = if settings.calculator { PrecedenceTable::calculator() } else { PrecedenceTable::new() };
+        if settings.tight_minus {
+            table.tight_minus();
+        }
+
+        table
+    }
+
+    // Some tools take -3^2 for (-3)^2, which is 9. The signs go right above the exponentiation then,
+    // and the postfix operators and functions move up to stay above them: -3! is still -(3!).
+    pub fn tight_minus(&mut self) -> &mut Self {
+        for operator in [TokenType::Function, TokenType::Bang, TokenType::PercentOf].iter() {
+            let (precedence, associativity) = (self.precedence(*operator), self.associativity(*operator));
+            self.set(*operator, precedence + 1, associativity);
+        }
+
+        let precedence = self.precedence(TokenType::Caret) + 1;
+        for operator in [TokenType::UMinus, TokenType::UPlus].iter() {
+            let associativity = self.associativity(*operator);
+            self.set(*operator, precedence, associativity);
+        }
+
+        self
+    }
+
+    pub fn set(&mut self, operator: TokenType, precedence: u8, associativity: Associativity) -> &mut Self {
+        self.operators.insert(operator, (precedence, associativity));
+        self
+    }
+
+    pub fn precedence(&self, ttype: TokenType) -> u8 {
+        match self.operators.get(&ttype) {
+            Some(&(precedence, _)) => precedence,
+            None => ttype.precedence(),
+        }
+    }
+
+    pub fn associativity(&self, ttype: TokenType) -> Associativity {
+        match self.operators.get(&ttype) {
+            Some(&(_, associativity)) => associativity,
+            None => ttype.associativity(),
+        }
+    }
+
+    // Whether the operator on top of the stack is applied before the incoming one is pushed.
+    //
+    // The unary minus and functions are prefix operators. There is no left operand yet,
+    // so nothing on the stack can be applied at this point. Nor can anything in a group that is still open.
+    //
+    // An operator of the same precedence is applied first only if they group left to right.
+    // For a right-associative one (the exponentiation) it stays on the stack: 2^3^2 is 2^(3^2).
+    pub fn should_pop(&self, top: TokenType, incoming: TokenType) -> bool {
+        if incoming.is_prefix() || top.is_opening() {
+            return false;
+        }
+
+        let top_precedence = self.precedence(top);
+        let incoming_precedence = self.precedence(incoming);
+
+        top_precedence > incoming_precedence
+            || (top_precedence == incoming_precedence && self.associativity(incoming) == Associativity::Left)
+    }
+
+}
+
+impl Default for PrecedenceTable {
+
+    fn default() -> Self {
+        PrecedenceTable::new()
+    }
+
+}
+
+// What the evaluation in the float mode may do differently.
+// finite: a result that is infinite while the operands are not is an error, not a value. So is 0 / 0, which is NaN.
+// allow_nan: a NaN out of finite operands, e.g. sqrt(-4), is a value too. By default it is an error.
+// degrees: the trigonometric functions take degrees instead of radians, so sin(90) is 1.
+// max_depth: how deeply groups may be nested. None is MAX_DEPTH.
+// max_input_length: how long the input may be, in bytes. None is MAX_INPUT_LENGTH.
+// seed: makes random() give the same numbers on every run. Only with the rand feature.
+// calculator: the binary operators are applied left to right regardless of precedence. See PrecedenceTable::calculator().
+// tight_minus: the unary minus binds tighter than ^, so -3^2 is 9. By default it is -(3^2), which is -9.
+// decimal_comma: 1,5 + 2,5 is 4. Then the arguments of a function are separated by ';' instead: max(1,5; 2).
+// exact: a literal that f64 cannot hold digit for digit is an error instead of being rounded. See Scanner::exact.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Settings {
+    pub finite: bool,
+    pub allow_nan: bool,
+    pub degrees: bool,
+    pub max_depth: Option<usize>,
+    pub max_input_length: Option<usize>,
+    pub seed: Option<u64>,
+    pub calculator: bool,
+    pub tight_minus: bool,
+    pub decimal_comma: bool,
+    pub exact: bool,
+}
+
+pub type BinaryFunction = fn(f64, f64) -> f64;
+
+// Custom binary operators on top of the built-in ones. A symbol is a single character,
+// and it cannot override a built-in operator: e.g. `+` is always scanned as the plus.
+//
+//     let mut registry = OperatorRegistry::new();
+//     registry.register('@', 3, Associativity::Left, |a, b| (a + b) / 2.0);
+//     registry.evaluate("4 @ 6") // Ok(5.0)
+pub struct OperatorRegistry {
+    table: PrecedenceTable,
+    functions: HashMap<char, BinaryFunction>,
+}
+
+impl OperatorRegistry {
+
+    pub fn new() -> Self {
+        OperatorRegistry { table: PrecedenceTable::new(), functions: HashMap::new() }
+    }
+
+    pub fn register(&mut self, symbol: char, precedence: u8, associativity: Associativity, function: BinaryFunction) -> &mut Self {
+        self.table.set(TokenType::Custom(symbol), precedence, associativity);
+        self.functions.insert(symbol, function);
+        self
+    }
+
+    pub fn evaluate(&self, input: &str) -> Result<f64, Error> {
+        check_length(input, None)?;
+        let mut scanner = Scanner::new(input);
+        scanner.custom = self.functions.keys().cloned().collect();
+        let tokens = scanner.scan_tokens()?;
+
+        let bindings = HashMap::new();
+        let mut output = Evaluation::new(&bindings, Settings::default());
+        output.custom = self.functions.clone();
+        Ok(shunting_yard(tokens, output, &self.table)?)
+    }
+
+}
+
+impl Default for OperatorRegistry {
+
+    fn default() -> Self {
+        OperatorRegistry::new()
+    }
+
+}
+
+const MAX_EXACT_INTEGER: f64 = 9007199254740992.0;
+
+// How deeply groups may be nested and how deep a tree may be. Nobody writes a thousand nested parentheses
+// or minuses by hand, and the trees are walked recursively: that deep they still fit in the 2 MB stack of a thread.
+pub const MAX_DEPTH: usize = 1000;
+
+// The scanner takes a dozen bytes per char of the input. An expression of a megabyte is not written by hand anyway.
+pub const MAX_INPUT_LENGTH: usize = 1 << 20;
+
+// The result of the previous line in a session.
+const ANS: &str = "ans";
+
+// Above 22! a factorial is not exact in f64 any more, and above 170! it is infinite.
+const MAX_EXACT_FACTORIAL: f64 = 22.0;
+
+// The names with the number of arguments. A variadic function takes at least that many.
+const FUNCTIONS: [(&str, usize); 22] = [
+    ("sqrt", 1), ("sin", 1), ("cos", 1), ("tan", 1), ("abs", 1),
+    ("ln", 1), ("log10", 1), ("log", 2),
+    ("round", 1), ("floor", 1), ("ceil", 1), ("trunc", 1), ("deg2rad", 1), ("rad2deg", 1),
+    ("max", 1), ("min", 1), ("sum", 1), ("avg", 1), ("pow", 2), ("gcd", 2), ("lcm", 2), ("clamp", 3),
+];
+
+// random() takes either no arguments or two. See Random.
+fn function_arity(name: &str) -> Option<usize> {
+    if cfg!(feature = "rand") && name == "random" {
+        return Some(2);
+    }
+
+    FUNCTIONS.iter().find(|function| function.0 == name).map(|function| function.1)
+}
+
+// max(5, 1, 9, 2) is 9.
+fn is_variadic(name: &str) -> bool {
+    name == "max" || name == "min" || name == "sum" || name == "avg"
+}
+
+// Whether a function can be called with that many arguments. Checked for calls parsed and read from JSON alike.
+fn accepts_arity(name: &str, arity: usize) -> bool {
+    let expected = match function_arity(name) {
+        Some(expected) => expected,
+        None => return false,
+    };
+
+    if is_variadic(name) {
+        return arity >= expected;
+    }
+
+    arity == expected || (name == "random" && arity == 0)
+}
+
+// Their argument is an angle. See Settings::degrees.
+fn is_trigonometric(name: &str) -> bool {
+    name == "sin" || name == "cos" || name == "tan"
+}
+
+// 1_200.50e3 and 1.2005e6 both have 12005. Zeros on either side do not count.
+fn significant_digits(number: &str) -> String {
+    let mantissa = number.split(['e', 'E']).next().unwrap();
+    let digits: String = mantissa.chars().filter(|c| c.is_ascii_digit()).collect();
+    digits.trim_start_matches('0').trim_end_matches('0').to_string()
+}
+
+// It is checked before the scanner is made, so an enormous input costs nothing but the check.
+// The span is the part over the limit.
+fn check_length(input: &str, limit: Option<usize>) -> Result<(), ScanError> {
+    let limit = limit.unwrap_or(MAX_INPUT_LENGTH);
+    if input.len() <= limit {
+        return Ok(());
+    }
+
+    let mut end = limit;
+    while !input.is_char_boundary(end) {
+        end -= 1;
+    }
+    let start = input[..end].chars().count();
+
+    Err(ScanError::InputTooLong { length: input.len(), limit, span: start..start + input[end..].chars().count() })
+}
+
+// The letter after the leading zero of an integer literal: 0b, 0o or 0x.
+fn radix(letter: char) -> Option<u32> {
+    match letter {
+        'b' | 'B' => Some(2),
+        'o' | 'O' => Some(8),
+        'x' | 'X' => Some(16),
+        _ => None,
+    }
+}
+
+// The lexeme is borrowed from the source, so a token cannot outlive it.
+// In the integer mode a number has its exact value in `integer` as well, since f64 cannot hold every i64.
+#[derive(Debug, Clone)]
+pub struct Token<'a> {
+    pub ttype: TokenType,
+    pub lexeme: &'a str,
+    pub literal: Option<f64>,
+    pub integer: Option<i64>,
+    pub start_at: usize,
+    pub end_at: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl<'a> Token<'a> {
+    pub fn position(&self) -> Position {
+        Position { line: self.line, column: self.column }
+    }
+
+    // Character offsets from the beginning of the source. The end is exclusive, unlike end_at.
+    pub fn span(&self) -> Range<usize> {
+        self.start_at..self.end_at + 1
+    }
+
+    fn number(&self) -> f64 {
+        match self.literal {
+            Some(x) => x,
+            None => unreachable!("Invalid number literal. Lexeme: {}.", self.lexeme),
+        }
+    }
+}
+
+// Both numbers start from 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+// E.g. "UMinus - at 4..5". The span tells apart two tokens with the same lexeme.
+impl<'a> fmt::Display for Token<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let span = self.span();
+        write!(f, "{:?} {} at {}..{}", self.ttype, self.lexeme, span.start, span.end)
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+#[derive(Debug)]
+pub enum ScanError {
+    UnexpectedCharacter { ch: char, position: Position, span: Range<usize> },
+    MissingDigits { lexeme: String, position: Position, span: Range<usize> },
+    InvalidNumber { lexeme: String, position: Position, span: Range<usize> },
+    MisplacedUnderscore { position: Position, span: Range<usize> },
+    UnknownFunction { name: String, position: Position, span: Range<usize> },
+    NotAnInteger { lexeme: String, position: Position, span: Range<usize> },
+    InputTooLong { length: usize, limit: usize, span: Range<usize> },
+    PrecisionLoss { lexeme: String, position: Position, span: Range<usize> },
+    IntegerOutOfRange { lexeme: String, position: Position, span: Range<usize> },
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScanError::UnexpectedCharacter { ch, position, .. } => write!(f, "Unexpected character: {} at {}.", ch, position),
+            ScanError::MissingDigits { lexeme, position, .. } => write!(f, "Missing digits after {} at {}.", lexeme, position),
+            ScanError::InvalidNumber { lexeme, position, .. } => write!(f, "Invalid number: {} at {}.", lexeme, position),
+            ScanError::MisplacedUnderscore { position, .. } => write!(f, "An underscore must be between digits at {}.", position),
+            ScanError::UnknownFunction { name, position, .. } => write!(f, "Unknown function: {} at {}.", name, position),
+            ScanError::NotAnInteger { lexeme, position, .. } => write!(f, "Not an integer: {} at {}.", lexeme, position),
+            ScanError::InputTooLong { length, limit, .. } => write!(f, "The input is {} bytes long, but at most {} are allowed.", length, limit),
+            ScanError::PrecisionLoss { lexeme, position, .. } => write!(f, "{} does not fit in a float exactly at {}.", lexeme, position),
+            ScanError::IntegerOutOfRange { lexeme, position, .. } => write!(f, "{} does not fit in a 64-bit integer at {}.", lexeme, position),
+        }
+    }
+}
+
+// The span is what an editor would underline: character offsets of the offending text in the source.
+impl ScanError {
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            ScanError::UnexpectedCharacter { span, .. }
+                | ScanError::MissingDigits { span, .. }
+                | ScanError::InvalidNumber { span, .. }
+                | ScanError::MisplacedUnderscore { span, .. }
+                | ScanError::UnknownFunction { span, .. }
+                | ScanError::NotAnInteger { span, .. }
+                | ScanError::InputTooLong { span, .. }
+                | ScanError::PrecisionLoss { span, .. }
+                | ScanError::IntegerOutOfRange { span, .. } => span.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    MismatchedRightParen { position: Position, span: Range<usize> },
+    MismatchedLeftParen { position: Position, span: Range<usize> },
+    MismatchedGrouping { opening: String, closing: String, position: Position, span: Range<usize> },
+    UnopenedBar { position: Position, span: Range<usize> },
+    MissingFunctionParen { position: Position, span: Range<usize> },
+    WrongArgumentCount { name: String, expected: usize, found: usize, position: Position, span: Range<usize> },
+    UnexpectedComma { position: Position, span: Range<usize> },
+    MismatchedConditional { position: Position, span: Range<usize> },
+    DivisionByZero { position: Position, span: Range<usize> },
+    InvalidFactorial { position: Position, span: Range<usize> },
+    InvalidBounds { position: Position, span: Range<usize> },
+    NotAWholeNumber { name: String, position: Position, span: Range<usize> },
+    UnknownIdentifier { name: String, position: Position, span: Range<usize> },
+    NoPreviousResult { position: Position, span: Range<usize> },
+    Overflow { position: Position, span: Range<usize> },
+    NegativeExponent { position: Position, span: Range<usize> },
+    FactorialOverflow { operand: i64, position: Position, span: Range<usize> },
+    NotANumber { lexeme: String, position: Position, span: Range<usize> },
+    NotInIntegerMode { lexeme: String, position: Position, span: Range<usize> },
+    DepthLimitExceeded { limit: usize, position: Position, span: Range<usize> },
+    UnexpectedAssignment { position: Position, span: Range<usize> },
+    MissingOperand { lexeme: String, position: Position, span: Range<usize> },
+    UnexpectedOperator { lexeme: String, position: Position, span: Range<usize> },
+    UnexpectedOperand { lexeme: String, position: Position, span: Range<usize> },
+    EmptyExpression,
+    CannotEvaluate,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::MismatchedRightParen { position, .. } => write!(f, "Mismatched right paren at {}.", position),
+            ParseError::MismatchedLeftParen { position, .. } => write!(f, "Mismatched left paren at {}.", position),
+            ParseError::MismatchedGrouping { opening, closing, position, .. } => write!(f, "Mismatched {} closed by {} at {}.", opening, closing, position),
+            ParseError::UnopenedBar { position, .. } => write!(f, "The bar closes an absolute value that is not open at {}. A bar after an operand closes one, so write 2 * |x| instead of 2|x|.", position),
+            ParseError::MissingFunctionParen { position, .. } => write!(f, "Expected a left paren after the function at {}.", position),
+            ParseError::WrongArgumentCount { name, expected, found, position, .. } => {
+                let least = if is_variadic(name) { "at least " } else { "" };
+                write!(f, "{} takes {}{} argument(s) but {} given at {}.", name, least, expected, found, position)
+            },
+            ParseError::UnexpectedComma { position, .. } => write!(f, "A comma outside of a function call at {}.", position),
+            ParseError::MismatchedConditional { position, .. } => write!(f, "A conditional needs both ? and : at {}.", position),
+            ParseError::DivisionByZero { position, .. } => write!(f, "Division by zero at {}.", position),
+            ParseError::InvalidFactorial { position, .. } => write!(f, "The factorial is defined only for non-negative integers at {}.", position),
+            ParseError::InvalidBounds { position, .. } => write!(f, "The lower bound is greater than the upper one at {}.", position),
+            ParseError::NotAWholeNumber { name, position, .. } => write!(f, "{} is defined only for non-negative integers at {}.", name, position),
+            ParseError::UnknownIdentifier { name, position, .. } => write!(f, "Unknown identifier: {} at {}.", name, position),
+            ParseError::NoPreviousResult { position, .. } => write!(f, "There is no previous result for ans at {}.", position),
+            ParseError::Overflow { position, .. } => write!(f, "Overflow at {}.", position),
+            ParseError::NegativeExponent { position, .. } => write!(f, "A negative exponent in the integer mode at {}.", position),
+            ParseError::FactorialOverflow { operand, position, .. } => write!(f, "{}! does not fit in an integer at {}.", operand, position),
+            ParseError::NotANumber { lexeme, position, .. } => write!(f, "{} does not give a number at {}.", lexeme, position),
+            ParseError::NotInIntegerMode { lexeme, position, .. } => write!(f, "{} is not supported in the integer mode at {}.", lexeme, position),
+            ParseError::DepthLimitExceeded { limit, position, .. } => write!(f, "The expression is nested deeper than {} levels at {}.", limit, position),
+            ParseError::UnexpectedAssignment { position, .. } => write!(f, "An assignment must be a whole line: name = expression at {}.", position),
+            ParseError::MissingOperand { lexeme, position, .. } => write!(f, "Expected an operand after {} at {}.", lexeme, position),
+            ParseError::UnexpectedOperator { lexeme, position, .. } => write!(f, "Expected an operand before {} at {}.", lexeme, position),
+            ParseError::UnexpectedOperand { lexeme, position, .. } => write!(f, "Expected an operator before {} at {}.", lexeme, position),
+            ParseError::EmptyExpression => write!(f, "The expression is empty."),
+            ParseError::CannotEvaluate => write!(f, "Cannot evaluate the expression to the concrete value."),
+        }
+    }
+}
+
+// An expression that cannot be evaluated as a whole has nothing particular to point at.
+impl ParseError {
+    pub fn span(&self) -> Option<Range<usize>> {
+        match self {
+            ParseError::MismatchedRightParen { span, .. }
+                | ParseError::MismatchedLeftParen { span, .. }
+                | ParseError::MismatchedGrouping { span, .. }
+                | ParseError::UnopenedBar { span, .. }
+                | ParseError::MissingFunctionParen { span, .. }
+                | ParseError::WrongArgumentCount { span, .. }
+                | ParseError::UnexpectedComma { span, .. }
+                | ParseError::MismatchedConditional { span, .. }
+                | ParseError::DivisionByZero { span, .. }
+                | ParseError::InvalidFactorial { span, .. }
+                | ParseError::InvalidBounds { span, .. }
+                | ParseError::NotAWholeNumber { span, .. }
+                | ParseError::UnknownIdentifier { span, .. }
+                | ParseError::NoPreviousResult { span, .. }
+                | ParseError::Overflow { span, .. }
+                | ParseError::NegativeExponent { span, .. }
+                | ParseError::FactorialOverflow { span, .. }
+                | ParseError::NotANumber { span, .. }
+                | ParseError::NotInIntegerMode { span, .. }
+                | ParseError::DepthLimitExceeded { span, .. }
+                | ParseError::UnexpectedAssignment { span, .. }
+                | ParseError::MissingOperand { span, .. }
+                | ParseError::UnexpectedOperator { span, .. }
+                | ParseError::UnexpectedOperand { span, .. } => Some(span.clone()),
+            ParseError::EmptyExpression | ParseError::CannotEvaluate => None,
+        }
+    }
+}
+
+// Something the evaluation went through with, but the result may not be what was meant.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    InexactFactorial { operand: f64, position: Position, span: Range<usize> },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::InexactFactorial { operand, position, .. } => write!(f, "{}! is only approximate at {}.", operand, position),
+        }
+    }
+}
+
+impl Warning {
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            Warning::InexactFactorial { span, .. } => span.clone(),
+        }
+    }
+}
 
 #[derive(Debug)]
-struct TokenType {
-    uid: u8,
-    precedence: u8,
+pub enum Error {
+    Scan(ScanError),
+    Parse(ParseError),
+}
+
+impl From<ScanError> for Error {
+    fn from(error: ScanError) -> Self {
+        Error::Scan(error)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(error: ParseError) -> Self {
+        Error::Parse(error)
+    }
+}
+
+impl Error {
+    pub fn span(&self) -> Option<Range<usize>> {
+        match self {
+            Error::Scan(error) => Some(error.span()),
+            Error::Parse(error) => error.span(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Scan(error) => write!(f, "{}", error),
+            Error::Parse(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+// So the errors can be passed on with `?` as Box<dyn std::error::Error>.
+impl error::Error for ScanError {}
+
+impl error::Error for ParseError {}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Scan(error) => Some(error),
+            Error::Parse(error) => Some(error),
+        }
+    }
+}
+
+pub struct Scanner<'a> {
+    source: &'a str,
+    chars: Vec<char>,
+    // Byte offsets of the chars in the source, and the length of the source at the end.
+    offsets: Vec<usize>,
+    tokens: Vec<Token<'a>>,
+    start: usize,
+    current: usize,
+    start_position: Position,
+    line: usize,
+    column: usize,
+    integer: bool,
+    // Whether a literal that f64 cannot hold digit for digit is an error. Otherwise it is silently rounded.
+    exact: bool,
+    // '.' or ','. With the decimal comma the arguments of a function are separated by ';': max(1,5; 2).
+    decimal_point: char,
+    custom: Vec<char>,
+    // How many tokens of the line the iterator has given out.
+    emitted: usize,
 }
 
-impl PartialEq for TokenType {
-    fn eq(&self, other: &Self) -> bool {
-        self.uid == other.uid
+impl<'a> Scanner<'a> {
+
+    pub fn new(source: &'a str) -> Self {
+        let mut offsets: Vec<usize> = source.char_indices().map(|(offset, _)| offset).collect();
+        offsets.push(source.len());
+
+        let mut scanner = Self {
+            source,
+            chars: source.chars().collect(),
+            offsets,
+            tokens: vec![],
+            start: 0,
+            current: 0,
+            start_position: Position { line: 1, column: 1 },
+            line: 1,
+            column: 1,
+            integer: false,
+            exact: false,
+            decimal_point: '.',
+            custom: vec![],
+            emitted: 0,
+        };
+        scanner.skip_blank_lines();
+
+        scanner
+    }
+
+    // Settings::decimal_comma and Settings::exact are for the scanner, the rest is for the evaluation.
+    pub fn with_settings(source: &'a str, settings: &Settings) -> Self {
+        let mut scanner = Scanner::new(source);
+        scanner.decimal_point = if settings.decimal_comma { ',' } else { '.' };
+        scanner.exact = settings.exact;
+        scanner
+    }
+
+    // The scanner stops at the end of a line. So if the source starts with a blank line,
+    // the first expression would never be reached by the functions that take only one.
+    // A line with just a comment is blank too.
+    fn skip_blank_lines(&mut self) {
+        loop {
+            let mut i = self.current;
+            while i < self.chars.len() && self.chars[i] != '\n' && self.chars[i] != '#' && self.chars[i].is_whitespace() {
+                i += 1;
+            }
+
+            if i < self.chars.len() && self.chars[i] == '#' {
+                while i < self.chars.len() && self.chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+
+            if i >= self.chars.len() || self.chars[i] != '\n' {
+                return;
+            }
+
+            while self.current <= i {
+                self.advance();
+            }
+        }
+    }
+
+    fn scan_tokens(&mut self) -> Result<&Vec<Token<'a>>, ScanError> {
+        for token in self.by_ref() {
+            token?;
+        }
+
+        Ok(&self.tokens)
+    }
+
+    // Unlike scan_tokens(), it does not stop at the first error.
+    // The result is all the errors found on the line.
+    fn scan_tokens_recovering(&mut self) -> Vec<ScanError> {
+        let mut errors = vec![];
+
+        while !self.is_at_end() && self.peek() != '\n' {
+            self.start = self.current;
+            self.start_position = self.current_position();
+
+            if let Err(error) = self.scan_token() {
+                errors.push(error);
+
+                // The rest of a malformed number or name would only produce more confusing errors.
+                while self.peek().is_alphanumeric() || self.peek() == '_' || self.peek() == self.decimal_point {
+                    self.advance();
+                }
+            }
+        }
+
+        errors
+    }
+
+    // The scanner stops at the end of a line (or at an error).
+    // This skips the rest of the line, so the next scan_tokens() call goes on with the next one.
+    fn next_line(&mut self) {
+        while !self.is_at_end() && self.peek() != '\n' {
+            self.advance();
+        }
+
+        if !self.is_at_end() {
+            self.advance();
+        }
+
+        self.tokens.clear();
+        self.emitted = 0;
+    }
+
+    fn current_position(&self) -> Position {
+        Position { line: self.line, column: self.column }
+    }
+
+    fn scan_token(&mut self) -> Result<(), ScanError> {
+        let c = self.advance();
+        match c {
+            '+' => {
+                if !self.follows_operand() {
+                    self.add_token(TokenType::UPlus, None);
+                } else {
+                    self.add_token(TokenType::Plus, None);
+                }
+            },
+            // Expressions copied from documents may contain the Unicode signs: − (U+2212), × (U+00D7), ÷ (U+00F7).
+            '-' | '−' => {
+                if !self.follows_operand() && self.integer && self.minimum_integer_follows() {
+                    self.digits()?;
+                    self.add_integer(i64::MIN)?;
+                } else if !self.follows_operand() {
+                    self.add_token(TokenType::UMinus, None);
+                } else {
+                    self.add_token(TokenType::Minus, None);
+                }
+            },
+            // Python writes the power as **, so 2 ** 3 ** 2 is 2 ^ 3 ^ 2.
+            '*' if self.match_char('*') => self.add_token(TokenType::Caret, None),
+            '*' | '×' => self.add_token(TokenType::Star, None),
+            // Floor division, as in Python: 7 // 2 is 3, -7 // 2 is -4.
+            '/' if self.match_char('/') => self.add_token(TokenType::DoubleSlash, None),
+            '/' | '÷' => self.add_token(TokenType::Slash, None),
+            // 10 % 3 is the remainder, but 50% is a half: the percent sign is postfix unless an operand follows it.
+            // A sign goes with that operand, so 10 % -3 and 10 % |x| are remainders too, and so are 50% - 3 and 50% + 1.
+            // A half minus three is (50%) - 3.
+            '%' if self.follows_operand() && !self.operand_follows() => self.add_token(TokenType::PercentOf, None),
+            '%' => self.add_token(TokenType::Percent, None),
+            '^' => self.add_token(TokenType::Caret, None),
+            _ if c == self.decimal_point && self.peek().is_ascii_digit() => self.number()?,
+            '!' if self.match_char('=') => self.add_token(TokenType::BangEqual, None),
+            '!' if self.follows_operand() => self.add_token(TokenType::Bang, None),
+            '=' if self.match_char('=') => self.add_token(TokenType::EqualEqual, None),
+            '=' => self.add_token(TokenType::Equal, None),
+            '<' => {
+                if self.match_char('=') {
+                    self.add_token(TokenType::LessEqual, None);
+                } else {
+                    self.add_token(TokenType::Less, None);
+                }
+            },
+            '>' => {
+                if self.match_char('=') {
+                    self.add_token(TokenType::GreaterEqual, None);
+                } else {
+                    self.add_token(TokenType::Greater, None);
+                }
+            },
+            '(' => {
+                self.add_implied_star();
+                self.add_token(TokenType::LeftParen, None);
+            },
+            ')' => self.add_token(TokenType::RightParen, None),
+            '[' => {
+                self.add_implied_star();
+                self.add_token(TokenType::LeftBracket, None);
+            },
+            ']' => self.add_token(TokenType::RightBracket, None),
+            '{' => {
+                self.add_implied_star();
+                self.add_token(TokenType::LeftBrace, None);
+            },
+            '}' => self.add_token(TokenType::RightBrace, None),
+            // Both bars look the same. Like with the minus, it depends on what comes before:
+            // a bar after an operand closes the group, any other one opens it. So |1 - |2 - 5|| has two of each.
+            // There is no implied multiplication before a bar, since it would close the group: 2|x| is an error.
+            '|' => {
+                if self.follows_operand() {
+                    self.add_token(TokenType::RightBar, None);
+                } else {
+                    self.add_token(TokenType::LeftBar, None);
+                }
+            },
+            ',' if self.decimal_point == '.' => self.add_token(TokenType::Comma, None),
+            ';' if self.decimal_point == ',' => self.add_token(TokenType::Comma, None),
+            '?' => self.add_token(TokenType::Question, None),
+            ':' => self.add_token(TokenType::Colon, None),
+            // A comment goes up to the end of the line: 1 + 2 # three
+            '#' => {
+                while !self.is_at_end() && self.peek() != '\n' {
+                    self.advance();
+                }
+            },
+            _ => {
+                // Tabs and carriage returns are whitespace too. A newline never gets here: it ends the line.
+                if c.is_whitespace() {
+                    return Ok(());
+                } else if c == '0' && radix(self.peek()).is_some() {
+                    self.radix_number()?;
+                } else if c.is_ascii_digit() {
+                    self.number()?;
+                } else if c.is_ascii_alphabetic() {
+                    self.identifier()?;
+                } else if self.custom.contains(&c) {
+                    self.add_token(TokenType::Custom(c), None);
+                } else {
+                    return Err(ScanError::UnexpectedCharacter { ch: c, position: self.start_position, span: self.start..self.current });
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.current >= self.chars.len()
+    }
+
+    fn advance(&mut self) -> char {
+        let c = self.chars[self.current];
+        self.current += 1;
+
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
+        c
+    }
+
+    // The second character of a two-character operator: consumed only if it is the expected one.
+    fn match_char(&mut self, expected: char) -> bool {
+        if self.is_at_end() || self.chars[self.current] != expected {
+            return false;
+        }
+
+        self.advance();
+        true
+    }
+
+    // Whether the last token ends an operand, so a minus is binary: 5 - 2, x - 2, (1 + 2) - 3, sqrt(4) - 1 (the paren closes the call),
+    // 3! - 1, |x| - 1. Anything else leaves the minus unary: -2, 2 * -2, -(1 + 2), -sqrt(4) (a function name is not an operand yet).
+    // The unary minus binds looser than ^, so -3^2 is -(3^2). Unless the settings say otherwise, see PrecedenceTable::tight_minus().
+    fn follows_operand(&self) -> bool {
+        let tokens_count = self.tokens.len();
+        tokens_count > 0 && (
+            self.tokens[tokens_count-1].ttype == TokenType::Number ||
+            self.tokens[tokens_count-1].ttype == TokenType::Identifier ||
+            self.tokens[tokens_count-1].ttype.is_closing() ||
+            self.tokens[tokens_count-1].ttype.is_postfix()
+        )
+    }
+
+    // The multiplication sign can be omitted before a paren, a constant or a function: 2(3+4), 2pi, (1+1)(2+2).
+    // Then a star token goes right before the current one.
+    fn add_implied_star(&mut self) {
+        if !self.follows_operand() {
+            return;
+        }
+
+        self.tokens.push(Token {
+            ttype: TokenType::Star,
+            literal: None,
+            integer: None,
+            lexeme: "*",
+            start_at: self.start,
+            end_at: self.start,
+            line: self.start_position.line,
+            column: self.start_position.column,
+        });
+    }
+
+    fn add_token(&mut self, ttype: TokenType, literal: Option<f64>) {
+        self.tokens.push(Token {
+            ttype,
+            literal,
+            integer: None,
+            lexeme: self.make_lexeme(),
+            start_at: self.start,
+            end_at: self.current - 1,
+            line: self.start_position.line,
+            column: self.start_position.column,
+        });
+    }
+
+    fn number(&mut self) -> Result<(), ScanError> {
+        // A number may start with a decimal point (.5). Then the point is already consumed,
+        // and there is only the fractional part. Parsing ".5" as f64 works fine too.
+        let leading_point = self.chars[self.start] == self.decimal_point;
+
+        self.digits()?;
+
+        if !leading_point && self.peek() == self.decimal_point && self.peek_next().is_ascii_digit() {
+            self.advance();
+
+            self.digits()?;
+        }
+
+        // An exponent is consumed only if there is at least one digit after "e" (and an optional sign).
+        // Otherwise "e" is not a part of the number.
+        if (self.peek() == 'e' || self.peek() == 'E') && (
+            self.peek_next().is_ascii_digit()
+            || ((self.peek_next() == '+' || self.peek_next() == '-') && self.peek_after_next().is_ascii_digit())
+        ) {
+            self.advance();
+
+            if self.peek() == '+' || self.peek() == '-' {
+                self.advance();
+            }
+
+            self.digits()?;
+        }
+
+        let literal = self.get_float_number()?;
+        if self.integer {
+            let value = self.integer_number()?;
+            return self.add_integer(value);
+        }
+
+        // 12345678901234567890 comes back as 12345678901234567000. The shortest form
+        // that gives the same f64 has fewer significant digits than the literal then.
+        if self.exact && significant_digits(&format!("{:e}", literal)) != significant_digits(self.make_lexeme()) {
+            return Err(ScanError::PrecisionLoss { lexeme: self.make_lexeme().to_string(), position: self.start_position, span: self.start..self.current });
+        }
+
+        self.add_number(literal)
+    }
+
+    // Underscores may separate groups of digits (1_000_000), but only if there are digits on both sides.
+    fn digits(&mut self) -> Result<(), ScanError> {
+        while self.peek().is_ascii_digit() || self.peek() == '_' {
+            if self.peek() == '_' && !self.peek_next().is_ascii_digit() {
+                return Err(ScanError::MisplacedUnderscore { position: self.current_position(), span: self.current..self.current + 1 });
+            }
+
+            self.advance();
+        }
+
+        Ok(())
+    }
+
+    fn identifier(&mut self) -> Result<(), ScanError> {
+        while self.peek().is_ascii_alphanumeric() || self.peek() == '_' {
+            self.advance();
+        }
+
+        let name = self.make_lexeme();
+        self.add_implied_star();
+
+        // Constants take precedence over variables, so a variable cannot be named "pi" or "e".
+        // Any other name is a variable. Whether it is bound is known only at evaluation time.
+        match name {
+            "pi" => self.add_number(consts::PI)?,
+            "e" => self.add_number(consts::E)?,
+            _ if function_arity(name).is_some() => self.add_token(TokenType::Function, None),
+            _ if self.peek() == '(' => return Err(ScanError::UnknownFunction { name: name.to_string(), position: self.start_position, span: self.start..self.current }),
+            _ => self.add_token(TokenType::Identifier, None),
+        }
+
+        Ok(())
+    }
+
+    // 0b1010, 0o17 and 0x1F. A decimal digit that is not valid in the base makes the whole literal invalid,
+    // so 0b102 is an error rather than 0b10 times 2.
+    fn radix_number(&mut self) -> Result<(), ScanError> {
+        let radix = radix(self.advance()).unwrap();
+
+        while self.peek().is_digit(radix.max(10)) {
+            self.advance();
+        }
+
+        if self.current - self.start == 2 {
+            return Err(ScanError::MissingDigits { lexeme: self.make_lexeme().to_string(), position: self.start_position, span: self.start..self.current });
+        }
+
+        let digits: String = self.chars[self.start+2..self.current].iter().collect();
+        if self.integer {
+            return match i64::from_str_radix(&digits, radix) {
+                Ok(value) => self.add_integer(value),
+                Err(ref error) if *error.kind() == IntErrorKind::PosOverflow => Err(self.out_of_range()),
+                Err(_) => Err(ScanError::InvalidNumber { lexeme: self.make_lexeme().to_string(), position: self.start_position, span: self.start..self.current }),
+            };
+        }
+
+        match u64::from_str_radix(&digits, radix) {
+            Ok(value) if self.exact && (value as f64) as u128 != value as u128 => {
+                Err(ScanError::PrecisionLoss { lexeme: self.make_lexeme().to_string(), position: self.start_position, span: self.start..self.current })
+            },
+            Ok(value) => self.add_number(value as f64),
+            Err(_) => Err(ScanError::InvalidNumber { lexeme: self.make_lexeme().to_string(), position: self.start_position, span: self.start..self.current }),
+        }
+    }
+
+    // Literals come to the integer mode through add_integer(), so here it is only pi and e.
+    fn add_number(&mut self, literal: f64) -> Result<(), ScanError> {
+        if self.integer && literal.fract() != 0.0 {
+            return Err(self.not_an_integer());
+        }
+
+        self.add_token(TokenType::Number, Some(literal));
+        Ok(())
+    }
+
+    fn add_integer(&mut self, value: i64) -> Result<(), ScanError> {
+        self.add_token(TokenType::Number, Some(value as f64));
+        self.tokens.last_mut().unwrap().integer = Some(value);
+        Ok(())
+    }
+
+    // The literal read straight into i64, not through f64. The point and the exponent only shift the digits,
+    // so 1.5e3 is 1500 and 100e-2 is 1, while 1.5 and 1e-3 are not integers.
+    fn integer_number(&self) -> Result<i64, ScanError> {
+        let lexeme: String = self.make_lexeme().chars().filter(|c| *c != '_').collect();
+        let (mantissa, exponent) = match lexeme.find(['e', 'E']) {
+            Some(index) => (&lexeme[..index], &lexeme[index + 1..]),
+            None => (&lexeme[..], "0"),
+        };
+        let (whole, fraction) = match mantissa.find(self.decimal_point) {
+            Some(index) => (&mantissa[..index], &mantissa[index + self.decimal_point.len_utf8()..]),
+            None => (mantissa, ""),
+        };
+
+        let digits = format!("{}{}", whole, fraction);
+        let digits = digits.trim_start_matches('0');
+        if digits.is_empty() {
+            return Ok(0);
+        }
+
+        // An exponent too long for i64 shifts any digits out of range one way or the other.
+        let shift = match exponent.parse::<i64>() {
+            Ok(exponent) => exponent.saturating_sub(fraction.len() as i64),
+            Err(_) if exponent.starts_with('-') => return Err(self.not_an_integer()),
+            Err(_) => return Err(self.out_of_range()),
+        };
+
+        if shift < 0 {
+            let kept = digits.len() as i64 + shift;
+            if kept <= 0 || digits[kept as usize..].chars().any(|c| c != '0') {
+                return Err(self.not_an_integer());
+            }
+
+            return digits[..kept as usize].parse::<i64>().map_err(|_| self.out_of_range());
+        }
+
+        // i64 has 19 digits at most, so there is no need to write out the zeros of 1e1000000.
+        if digits.len() as i64 + shift > 19 {
+            return Err(self.out_of_range());
+        }
+
+        format!("{}{}", digits, "0".repeat(shift as usize)).parse::<i64>().map_err(|_| self.out_of_range())
+    }
+
+    fn not_an_integer(&self) -> ScanError {
+        ScanError::NotAnInteger { lexeme: self.make_lexeme().to_string(), position: self.start_position, span: self.start..self.current }
+    }
+
+    fn out_of_range(&self) -> ScanError {
+        ScanError::IntegerOutOfRange { lexeme: self.make_lexeme().to_string(), position: self.start_position, span: self.start..self.current }
+    }
+
+    // i64::MIN has no positive counterpart: 9223372036854775808 is out of range, while -9223372036854775808 is not.
+    // So the integer mode negates that literal before the range check, and the minus becomes a part of it.
+    fn minimum_integer_follows(&self) -> bool {
+        let mut digits = String::new();
+        let mut i = self.current;
+        while i < self.chars.len() && (self.chars[i].is_ascii_digit() || self.chars[i] == '_') {
+            digits.extend(self.chars[i..=i].iter().filter(|c| c.is_ascii_digit()));
+            i += 1;
+        }
+
+        // 9223372036854775808.0 and 9.223372036854775808e18 take the usual way.
+        let next = self.chars.get(i).cloned().unwrap_or('\0');
+        digits.trim_start_matches('0') == "9223372036854775808" && next != self.decimal_point && next != 'e' && next != 'E'
+    }
+
+    // Whether an operand comes next, maybe after signs. The whitespace makes no difference: 10 % -3, 10 % - 3
+    // and 10%-3 read the same. A bar opens an operand too, unless a bar is open already: in |50%| + 1 it closes that one.
+    fn operand_follows(&self) -> bool {
+        let bar_opens = !self.inside_bars();
+        let mut i = self.current;
+        while i < self.chars.len() && self.chars[i] != '\n' && (
+            self.chars[i].is_whitespace() || "-+−".contains(self.chars[i]) || (self.chars[i] == '|' && bar_opens)
+        ) {
+            i += 1;
+        }
+
+        i < self.chars.len() && (
+            self.chars[i].is_ascii_alphanumeric() || self.chars[i] == self.decimal_point || "([{".contains(self.chars[i])
+        )
+    }
+
+    fn inside_bars(&self) -> bool {
+        let opened = self.tokens.iter().filter(|token| token.ttype == TokenType::LeftBar).count();
+        let closed = self.tokens.iter().filter(|token| token.ttype == TokenType::RightBar).count();
+        opened > closed
+    }
+
+    fn peek(&self) -> char {
+        if self.is_at_end() {
+            return '\0';
+        }
+
+        self.chars[self.current]
+    }
+
+    fn peek_next(&self) -> char {
+        if self.current + 1 >= self.chars.len() {
+            return '\0';
+        }
+
+        self.chars[self.current+1]
+    }
+
+    fn peek_after_next(&self) -> char {
+        if self.current + 2 >= self.chars.len() {
+            return '\0';
+        }
+
+        self.chars[self.current+2]
+    }
+
+    // The scanner counts chars, but the source is sliced by bytes.
+    fn make_lexeme(&self) -> &'a str {
+        &self.source[self.offsets[self.start]..self.offsets[self.current]]
+    }
+
+    // number() lets only valid literals through, but a scanner error is still better than a panic.
+    fn get_float_number(&self) -> Result<f64, ScanError> {
+        match self.make_lexeme().replace('_', "").replace(self.decimal_point, ".").parse::<f64>() {
+            Ok(literal) => Ok(literal),
+            Err(_) => Err(ScanError::InvalidNumber { lexeme: self.make_lexeme().to_string(), position: self.start_position, span: self.start..self.current }),
+        }
+    }
+
+}
+
+// Tokens are scanned one by one as they are asked for, up to the end of the line, like scan_tokens() does.
+// The scanner still keeps the tokens of the line: the unary minus and the implied multiplication look back at them.
+impl<'a> Iterator for Scanner<'a> {
+    type Item = Result<Token<'a>, ScanError>;
+
+    fn next(&mut self) -> Option<Result<Token<'a>, ScanError>> {
+        // Whitespace gives no token, the implied multiplication gives two.
+        while self.emitted == self.tokens.len() {
+            if self.is_at_end() || self.peek() == '\n' {
+                return None;
+            }
+
+            self.start = self.current;
+            self.start_position = self.current_position();
+            if let Err(error) = self.scan_token() {
+                return Some(Err(error));
+            }
+        }
+
+        self.emitted += 1;
+        Some(Ok(self.tokens[self.emitted - 1].clone()))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Variable(String),
+    BinaryOp { op: TokenType, left: Box<Expr>, right: Box<Expr> },
+    UnaryOp { op: TokenType, operand: Box<Expr> },
+    Function { name: String, arguments: Vec<Expr> },
+    Conditional { condition: Box<Expr>, then: Box<Expr>, otherwise: Box<Expr> },
+}
+
+// The shunting yard algorithm only decides in which order operands and operators come out.
+// What happens to them then is up to an output: they can be evaluated right away or composed into a tree.
+trait Output {
+    type Value;
+
+    fn push(&mut self, number: &Token);
+    fn variable(&mut self, name: &Token) -> Result<(), ParseError>;
+    fn apply(&mut self, operator: &Token) -> Result<(), ParseError>;
+    // The arguments are the last `arity` values, the first of them comes first.
+    fn call(&mut self, function: &Token, arity: usize) -> Result<(), ParseError>;
+    fn finish(self) -> Result<Self::Value, ParseError>;
+
+    // Only the evaluation can be traced. See Parser::trace().
+    fn trace(&self) -> bool {
+        false
+    }
+
+    fn state(&self) -> String {
+        String::new()
+    }
+
+    fn max_depth(&self) -> usize {
+        MAX_DEPTH
+    }
+
+    // Only an explanation wants to know why the operators come in this order. See Parser::explain().
+    fn explains(&self) -> bool {
+        false
+    }
+
+    fn explain(&mut self, _reason: String) {}
+}
+
+struct Evaluation<'a> {
+    results: Vec<Result<f64, ParseError>>,
+    bindings: &'a HashMap<String, f64>,
+    custom: HashMap<char, BinaryFunction>,
+    settings: Settings,
+    trace: bool,
+    random: Random,
+    warnings: Vec<Warning>,
+}
+
+impl<'a> Evaluation<'a> {
+
+    fn new(bindings: &'a HashMap<String, f64>, settings: Settings) -> Self {
+        Evaluation { results: vec![], bindings, custom: HashMap::new(), settings, trace: false, random: Random::new(settings.seed), warnings: vec![] }
+    }
+}
+
+impl<'a> Output for Evaluation<'a> {
+    type Value = f64;
+
+    fn push(&mut self, number: &Token) {
+        self.results.push(Ok(number.number()));
+    }
+
+    fn variable(&mut self, name: &Token) -> Result<(), ParseError> {
+        match self.bindings.get(name.lexeme) {
+            Some(value) => self.results.push(Ok(*value)),
+            None if name.lexeme == ANS => self.results.push(Err(ParseError::NoPreviousResult { position: name.position(), span: name.span() })),
+            None => self.results.push(Err(ParseError::UnknownIdentifier { name: name.lexeme.to_string(), position: name.position(), span: name.span() })),
+        }
+
+        Ok(())
+    }
+
+    fn apply(&mut self, operator: &Token) -> Result<(), ParseError> {
+        let custom = &self.custom;
+        let finite = self.settings.finite;
+        let allow_nan = self.settings.allow_nan;
+        let warnings = &mut self.warnings;
+        apply_deferred(&mut self.results, operator, |operator, operands| {
+            let finite_operands = operands.iter().all(|operand| operand.is_finite());
+            let first = operands[0];
+
+            // 0 / 0 is NaN, so here it is not a number rather than a division by zero.
+            if finite && operator.ttype.is_division() && operands[..] == [0.0, 0.0] {
+                return Err(ParseError::NotANumber { lexeme: operator.lexeme.to_string(), position: operator.position(), span: operator.span() });
+            }
+
+            let result = match operator.ttype {
+                TokenType::Custom(symbol) => custom[&symbol](operands[0], operands[1]),
+                _ => evaluate(operator, operands)?,
+            };
+
+            // An infinite factorial speaks for itself.
+            if operator.ttype == TokenType::Bang && first > MAX_EXACT_FACTORIAL && result.is_finite() {
+                warnings.push(Warning::InexactFactorial { operand: first, position: operator.position(), span: operator.span() });
+            }
+
+            if !allow_nan && finite_operands && result.is_nan() {
+                return Err(ParseError::NotANumber { lexeme: operator.lexeme.to_string(), position: operator.position(), span: operator.span() });
+            }
+
+            if finite && finite_operands && result.is_infinite() {
+                return Err(ParseError::Overflow { position: operator.position(), span: operator.span() });
+            }
+
+            Ok(result)
+        });
+        Ok(())
+    }
+
+    fn call(&mut self, function: &Token, arity: usize) -> Result<(), ParseError> {
+        let degrees = self.settings.degrees;
+        let allow_nan = self.settings.allow_nan;
+        let random = &mut self.random;
+        let arguments = self.results.split_off(self.results.len() - arity);
+        let result = arguments.into_iter().collect::<Result<Vec<f64>, ParseError>>()
+            .and_then(|mut arguments| {
+                if function.lexeme == "random" {
+                    let (low, high) = if arguments.is_empty() { (0.0, 1.0) } else { (arguments[0], arguments[1]) };
+                    if low.is_nan() || high.is_nan() || low >= high || !(high - low).is_finite() {
+                        return Err(ParseError::InvalidBounds { position: function.position(), span: function.span() });
+                    }
+
+                    return Ok(random.between(low, high));
+                }
+
+                if function.lexeme == "clamp" && (arguments[1].is_nan() || arguments[2].is_nan() || arguments[1] > arguments[2]) {
+                    return Err(ParseError::InvalidBounds { position: function.position(), span: function.span() });
+                }
+
+                if (function.lexeme == "gcd" || function.lexeme == "lcm") && !arguments.iter().all(|argument| whole_number(*argument).is_some()) {
+                    return Err(ParseError::NotAWholeNumber { name: function.lexeme.to_string(), position: function.position(), span: function.span() });
+                }
+
+                if degrees && is_trigonometric(function.lexeme) {
+                    arguments[0] = arguments[0].to_radians();
+                }
+
+                let finite_arguments = arguments.iter().all(|argument| argument.is_finite());
+                let result = calculate_function(function.lexeme, &arguments);
+                if !allow_nan && finite_arguments && result.is_nan() {
+                    return Err(ParseError::NotANumber { lexeme: function.lexeme.to_string(), position: function.position(), span: function.span() });
+                }
+
+                Ok(result)
+            });
+        self.results.push(result);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<f64, ParseError> {
+        if self.results.len() != 1 {
+            return Err(ParseError::CannotEvaluate);
+        }
+
+        self.results.pop().unwrap()
+    }
+
+    fn trace(&self) -> bool {
+        self.trace
+    }
+
+    fn state(&self) -> String {
+        deferred_state(&self.results)
+    }
+
+    fn max_depth(&self) -> usize {
+        self.settings.max_depth.unwrap_or(MAX_DEPTH)
+    }
+}
+
+// The result along with the warnings of the evaluation.
+struct Warned<'a> {
+    evaluation: Evaluation<'a>,
+}
+
+impl<'a> Output for Warned<'a> {
+    type Value = (f64, Vec<Warning>);
+
+    fn push(&mut self, number: &Token) {
+        self.evaluation.push(number);
+    }
+
+    fn variable(&mut self, name: &Token) -> Result<(), ParseError> {
+        self.evaluation.variable(name)
+    }
+
+    fn apply(&mut self, operator: &Token) -> Result<(), ParseError> {
+        self.evaluation.apply(operator)
+    }
+
+    fn call(&mut self, function: &Token, arity: usize) -> Result<(), ParseError> {
+        self.evaluation.call(function, arity)
+    }
+
+    fn finish(mut self) -> Result<(f64, Vec<Warning>), ParseError> {
+        let warnings = mem::take(&mut self.evaluation.warnings);
+        self.evaluation.finish().map(|result| (result, warnings))
+    }
+
+    fn trace(&self) -> bool {
+        self.evaluation.trace()
+    }
+
+    fn state(&self) -> String {
+        self.evaluation.state()
+    }
+
+    fn max_depth(&self) -> usize {
+        self.evaluation.max_depth()
+    }
+}
+
+// The evaluation written down as a worked solution, one line per applied operator or function:
+// 2 + 3 * 4 is "3 * 4 = 12" and "2 + 12 = 14". Unlike the trace, there are no stacks, just the arithmetic.
+// A step with an error in it, like the branch of a conditional that is not taken, is left out.
+struct Steps<'a> {
+    evaluation: Evaluation<'a>,
+    steps: Vec<String>,
+}
+
+impl<'a> Steps<'a> {
+
+    // The last `count` values, if they all are numbers.
+    fn operands(&self, count: usize) -> Option<Vec<f64>> {
+        let results = &self.evaluation.results;
+        results[results.len() - count..].iter().map(|result| result.as_ref().ok().cloned()).collect()
+    }
+
+    fn record(&mut self, operands: Option<Vec<f64>>, describe: &dyn Fn(&[f64]) -> String) {
+        let result = match self.evaluation.results.last() {
+            Some(Ok(result)) => *result,
+            _ => return,
+        };
+
+        // Negating a number is no step to write down: -4 = -4. But -(-4) = 4 is.
+        if let Some(operands) = operands {
+            let description = describe(&operands);
+            if description != result.to_string() {
+                self.steps.push(format!("{} = {}", description, result));
+            }
+        }
+    }
+}
+
+// 2 * (-3), not 2 * -3.
+fn step_operand(operand: f64) -> String {
+    if operand < 0.0 {
+        return format!("({})", operand);
+    }
+
+    format!("{}", operand)
+}
+
+impl<'a> Output for Steps<'a> {
+    type Value = (f64, Vec<String>);
+
+    fn push(&mut self, number: &Token) {
+        self.evaluation.push(number);
+    }
+
+    fn variable(&mut self, name: &Token) -> Result<(), ParseError> {
+        self.evaluation.variable(name)
+    }
+
+    fn apply(&mut self, operator: &Token) -> Result<(), ParseError> {
+        let arity = if operator.ttype == TokenType::Colon { 3 } else if operator.ttype.is_unary() { 1 } else { 2 };
+        let operands = self.operands(arity);
+        self.evaluation.apply(operator)?;
+
+        let lexeme = operator.lexeme;
+        self.record(operands, &|operands| match operator.ttype {
+            TokenType::Colon => format!("{} ? {} : {}", operands[0], operands[1], operands[2]),
+            ttype if ttype.is_postfix() => format!("{}{}", step_operand(operands[0]), lexeme),
+            ttype if ttype.is_unary() => format!("{}{}", lexeme, step_operand(operands[0])),
+            _ => format!("{} {} {}", operands[0], lexeme, step_operand(operands[1])),
+        });
+        Ok(())
+    }
+
+    fn call(&mut self, function: &Token, arity: usize) -> Result<(), ParseError> {
+        let operands = self.operands(arity);
+        self.evaluation.call(function, arity)?;
+
+        let name = function.lexeme;
+        self.record(operands, &|operands| {
+            let arguments: Vec<String> = operands.iter().map(|operand| operand.to_string()).collect();
+            format!("{}({})", name, arguments.join(", "))
+        });
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(f64, Vec<String>), ParseError> {
+        let steps = self.steps;
+        self.evaluation.finish().map(|result| (result, steps))
+    }
+
+    fn max_depth(&self) -> usize {
+        self.evaluation.max_depth()
+    }
+}
+
+// random() is in [0, 1) and random(lo, hi) is in [lo, hi). The generator is made only when it is needed,
+// seeded from the OS unless Settings::seed is given. Without the rand feature there is no random() at all.
+#[cfg(feature = "rand")]
+struct Random {
+    seed: Option<u64>,
+    rng: Option<rand::rngs::StdRng>,
+}
+
+#[cfg(feature = "rand")]
+impl Random {
+
+    fn new(seed: Option<u64>) -> Self {
+        Random { seed, rng: None }
+    }
+
+    fn between(&mut self, low: f64, high: f64) -> f64 {
+        use rand::{Rng, SeedableRng};
+
+        let seed = self.seed;
+        let rng = self.rng.get_or_insert_with(|| match seed {
+            Some(seed) => rand::rngs::StdRng::seed_from_u64(seed),
+            None => rand::rngs::StdRng::from_entropy(),
+        });
+        rng.gen_range(low..high)
+    }
+}
+
+#[cfg(not(feature = "rand"))]
+struct Random;
+
+#[cfg(not(feature = "rand"))]
+impl Random {
+
+    fn new(_seed: Option<u64>) -> Self {
+        Random
+    }
+
+    fn between(&mut self, _low: f64, _high: f64) -> f64 {
+        unreachable!("random() is only there with the rand feature.")
+    }
+}
+
+// The same as Evaluation, but with exact integers. Overflows are errors instead of wrapping.
+struct IntegerEvaluation {
+    results: Vec<Result<i64, ParseError>>,
+    trace: bool,
+}
+
+impl Output for IntegerEvaluation {
+    type Value = i64;
+
+    fn push(&mut self, number: &Token) {
+        self.results.push(Ok(number.integer.unwrap_or(number.number() as i64)));
+    }
+
+    fn variable(&mut self, name: &Token) -> Result<(), ParseError> {
+        self.results.push(Err(ParseError::UnknownIdentifier { name: name.lexeme.to_string(), position: name.position(), span: name.span() }));
+        Ok(())
+    }
+
+    fn apply(&mut self, operator: &Token) -> Result<(), ParseError> {
+        apply_deferred(&mut self.results, operator, evaluate_integer);
+        Ok(())
+    }
+
+    fn call(&mut self, function: &Token, _arity: usize) -> Result<(), ParseError> {
+        Err(ParseError::NotInIntegerMode { lexeme: function.lexeme.to_string(), position: function.position(), span: function.span() })
+    }
+
+    fn finish(mut self) -> Result<i64, ParseError> {
+        if self.results.len() != 1 {
+            return Err(ParseError::CannotEvaluate);
+        }
+
+        self.results.pop().unwrap()
+    }
+
+    fn trace(&self) -> bool {
+        self.trace
+    }
+
+    fn state(&self) -> String {
+        deferred_state(&self.results)
+    }
+}
+
+// An error stays on the stack in place of the value instead of being returned right away.
+// It comes out only if the value is actually used: the branch of a conditional that is not taken
+// may divide by zero as it wants. Otherwise it makes its way up to the result.
+fn apply_deferred<T, F>(results: &mut Vec<Result<T, ParseError>>, operator: &Token, mut evaluate: F)
+    where T: Copy + Default + PartialEq, F: FnMut(&Token, &mut Vec<T>) -> Result<T, ParseError>
+{
+    if operator.ttype == TokenType::Colon {
+        let otherwise = results.pop().unwrap();
+        let then = results.pop().unwrap();
+        let condition = results.pop().unwrap();
+
+        // Nonzero is true.
+        results.push(condition.and_then(|condition| if condition != T::default() { then } else { otherwise }));
+        return;
+    }
+
+    let arity = if operator.ttype.is_unary() { 1 } else { 2 };
+    let operands = results.split_off(results.len() - arity);
+    let result = operands.into_iter().collect::<Result<Vec<T>, ParseError>>()
+        .and_then(|mut operands| evaluate(operator, &mut operands));
+    results.push(result);
+}
+
+fn deferred_state<T: fmt::Debug>(results: &[Result<T, ParseError>]) -> String {
+    let values: Vec<String> = results.iter().map(|result| match result {
+        Ok(value) => format!("{:?}", value),
+        Err(_) => String::from("error"),
+    }).collect();
+
+    format!("[{}]", values.join(", "))
+}
+
+// Every node comes with the depth of its tree. A deeper tree than max_depth() is an error, because eval(),
+// to_infix() and even dropping the tree walk it recursively, so a long chain like 1 ^ 1 ^ ... ^ 1 would overflow the stack.
+struct TreeBuilder {
+    nodes: Vec<(Expr, usize)>,
+}
+
+impl TreeBuilder {
+
+    fn pop(&mut self) -> (Box<Expr>, usize) {
+        let (node, depth) = self.nodes.pop().unwrap();
+        (Box::new(node), depth)
+    }
+
+    fn add_node(&mut self, node: Expr, depth: usize, token: &Token) -> Result<(), ParseError> {
+        if depth > self.max_depth() {
+            return Err(ParseError::DepthLimitExceeded { limit: self.max_depth(), position: token.position(), span: token.span() });
+        }
+
+        self.nodes.push((node, depth));
+        Ok(())
+    }
+}
+
+impl Output for TreeBuilder {
+    type Value = Expr;
+
+    fn push(&mut self, number: &Token) {
+        self.nodes.push((Expr::Number(number.number()), 1));
+    }
+
+    fn variable(&mut self, name: &Token) -> Result<(), ParseError> {
+        self.nodes.push((Expr::Variable(name.lexeme.to_string()), 1));
+        Ok(())
+    }
+
+    fn apply(&mut self, operator: &Token) -> Result<(), ParseError> {
+        let (operand, operand_depth) = self.pop();
+
+        if operator.ttype == TokenType::Colon {
+            let (then, then_depth) = self.pop();
+            let (condition, condition_depth) = self.pop();
+            let depth = condition_depth.max(then_depth).max(operand_depth) + 1;
+            return self.add_node(Expr::Conditional { condition, then, otherwise: operand }, depth, operator);
+        }
+
+        if operator.ttype.is_unary() {
+            return self.add_node(Expr::UnaryOp { op: operator.ttype, operand }, operand_depth + 1, operator);
+        }
+
+        let (left, left_depth) = self.pop();
+        self.add_node(Expr::BinaryOp { op: operator.ttype, left, right: operand }, left_depth.max(operand_depth) + 1, operator)
+    }
+
+    fn call(&mut self, function: &Token, arity: usize) -> Result<(), ParseError> {
+        let (arguments, depths): (Vec<Expr>, Vec<usize>) = self.nodes.split_off(self.nodes.len() - arity).into_iter().unzip();
+        let depth = depths.into_iter().max().unwrap_or(0) + 1;
+        self.add_node(Expr::Function { name: function.lexeme.to_string(), arguments }, depth, function)
+    }
+
+    fn finish(mut self) -> Result<Expr, ParseError> {
+        if self.nodes.len() != 1 {
+            return Err(ParseError::CannotEvaluate);
+        }
+
+        Ok(self.nodes.pop().unwrap().0)
+    }
+}
+
+struct RpnWriter {
+    lexemes: Vec<String>,
+}
+
+impl Output for RpnWriter {
+    type Value = Vec<String>;
+
+    fn push(&mut self, number: &Token) {
+        self.lexemes.push(number.lexeme.to_string());
+    }
+
+    fn variable(&mut self, name: &Token) -> Result<(), ParseError> {
+        self.lexemes.push(name.lexeme.to_string());
+        Ok(())
+    }
+
+    // The unary minus looks the same as the binary one.
+    // But a stack machine has to know how many operands to take, so it goes as "neg".
+    // The unary plus does nothing at all, so it is omitted.
+    // The conditional takes three operands, the condition comes first.
+    fn apply(&mut self, operator: &Token) -> Result<(), ParseError> {
+        if operator.ttype == TokenType::UMinus {
+            self.lexemes.push(String::from("neg"));
+        } else if operator.ttype == TokenType::PercentOf {
+            self.lexemes.push(String::from("percent"));
+        } else if operator.ttype == TokenType::Colon {
+            self.lexemes.push(String::from("?:"));
+        } else if operator.ttype != TokenType::UPlus {
+            self.lexemes.push(operator.lexeme.to_string());
+        }
+
+        Ok(())
+    }
+
+    // Every function takes a fixed number of arguments, so the name is enough.
+    fn call(&mut self, function: &Token, _arity: usize) -> Result<(), ParseError> {
+        self.lexemes.push(function.lexeme.to_string());
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Vec<String>, ParseError> {
+        Ok(self.lexemes)
+    }
+}
+
+// Nothing is computed at all. The parser counts the operands itself and already tells
+// whether an operator finds all of its operands and exactly one value is left in the end.
+struct Validation;
+
+impl Output for Validation {
+    type Value = ();
+
+    fn push(&mut self, _number: &Token) {}
+
+    fn variable(&mut self, _name: &Token) -> Result<(), ParseError> {
+        Ok(())
+    }
+
+    fn apply(&mut self, _operator: &Token) -> Result<(), ParseError> {
+        Ok(())
+    }
+
+    fn call(&mut self, _function: &Token, _arity: usize) -> Result<(), ParseError> {
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), ParseError> {
+        Ok(())
+    }
+}
+
+// The decisions of the parser in words: "Applying * before + because * has higher precedence."
+// Nothing is evaluated, so it explains 1 / 0 just as well.
+struct Explanation {
+    reasons: Vec<String>,
+}
+
+impl Output for Explanation {
+    type Value = Vec<String>;
+
+    fn push(&mut self, _number: &Token) {}
+
+    fn variable(&mut self, _name: &Token) -> Result<(), ParseError> {
+        Ok(())
+    }
+
+    fn apply(&mut self, _operator: &Token) -> Result<(), ParseError> {
+        Ok(())
+    }
+
+    fn call(&mut self, _function: &Token, _arity: usize) -> Result<(), ParseError> {
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Vec<String>, ParseError> {
+        Ok(self.reasons)
+    }
+
+    fn explains(&self) -> bool {
+        true
+    }
+
+    fn explain(&mut self, reason: String) {
+        self.reasons.push(reason);
+    }
+}
+
+// An owned copy of a token: a program outlives the source it has been compiled from.
+#[derive(Debug, Clone)]
+struct Operation {
+    ttype: TokenType,
+    lexeme: String,
+    start_at: usize,
+    end_at: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Operation {
+
+    fn new(token: &Token) -> Self {
+        Operation {
+            ttype: token.ttype,
+            lexeme: token.lexeme.to_string(),
+            start_at: token.start_at,
+            end_at: token.end_at,
+            line: token.line,
+            column: token.column,
+        }
+    }
+
+    fn token<'a>(&'a self) -> Token<'a> {
+        Token {
+            ttype: self.ttype,
+            lexeme: &self.lexeme,
+            literal: None,
+            integer: None,
+            start_at: self.start_at,
+            end_at: self.end_at,
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Instruction {
+    Push(f64),
+    Load(Operation),
+    Apply(Operation),
+    Call(Operation, usize),
+}
+
+// The same calls the shunting yard makes to an evaluation, written down to be replayed later. See Program.
+struct Compiler {
+    instructions: Vec<Instruction>,
+}
+
+impl Output for Compiler {
+    type Value = Program;
+
+    fn push(&mut self, number: &Token) {
+        self.instructions.push(Instruction::Push(number.number()));
+    }
+
+    fn variable(&mut self, name: &Token) -> Result<(), ParseError> {
+        self.instructions.push(Instruction::Load(Operation::new(name)));
+        Ok(())
+    }
+
+    fn apply(&mut self, operator: &Token) -> Result<(), ParseError> {
+        self.instructions.push(Instruction::Apply(Operation::new(operator)));
+        Ok(())
+    }
+
+    fn call(&mut self, function: &Token, arity: usize) -> Result<(), ParseError> {
+        self.instructions.push(Instruction::Call(Operation::new(function), arity));
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Program, ParseError> {
+        Ok(Program { instructions: self.instructions })
+    }
+}
+
+// The shunting yard keeps its state here, the same way the scanner does.
+// The operands go straight to the output, the operators wait on the stack.
+struct Parser<'a, O: Output> {
+    tokens: &'a [Token<'a>],
+    current: usize,
+    output: O,
+    operators: Vec<&'a Token<'a>>,
+    // How many arguments there are in every open group. Only a function call may have more than one.
+    arguments: Vec<usize>,
+    // Operands and operators take turns: 1 + * 2 or 1 2 is caught right where it goes wrong.
+    // Prefix operators and opening brackets come where an operand is expected and keep expecting it.
+    expect_operand: bool,
+    // How many values the output holds. An operator short of its operands is an error then, not a panic in the output.
+    operands: usize,
+    table: &'a PrecedenceTable,
+}
+
+impl<'a, O: Output> Parser<'a, O> {
+
+    fn new(tokens: &'a [Token<'a>], output: O, table: &'a PrecedenceTable) -> Self {
+        Parser {
+            tokens,
+            current: 0,
+            output,
+            operators: vec![],
+            arguments: vec![],
+            expect_operand: true,
+            operands: 0,
+            table,
+        }
+    }
+
+    pub fn parse(mut self) -> Result<O::Value, ParseError> {
+        // There is nothing but whitespace.
+        if self.tokens.is_empty() {
+            return Err(ParseError::EmptyExpression);
+        }
+
+        while !self.is_at_end() {
+            let token = self.advance();
+            self.check_order(token)?;
+
+            if token.ttype == TokenType::Number {
+                self.operands += 1;
+                self.output.push(token);
+                self.trace(format_args!("push NUMBER {}", token.lexeme));
+            } else if token.ttype == TokenType::Identifier {
+                self.operands += 1;
+                self.output.variable(token)?;
+                self.trace(format_args!("push IDENTIFIER {}", token.lexeme));
+            } else if token.ttype.precedence() != 0 {
+                self.operator(token)?;
+            } else if token.ttype.is_opening() {
+                self.opening(token)?;
+            } else if token.ttype == TokenType::Question {
+                self.question(token)?;
+            } else if token.ttype == TokenType::Colon {
+                self.colon(token)?;
+            } else if token.ttype == TokenType::Comma {
+                self.comma(token)?;
+            } else if token.ttype == TokenType::Equal {
+                // An assignment is taken apart before the expression gets here. See assignment().
+                return Err(ParseError::UnexpectedAssignment { position: token.position(), span: token.span() });
+            } else if token.ttype.is_closing() {
+                self.closing(token)?;
+            }
+        }
+
+        // The last operator has nothing to apply to: 1 +
+        if self.expect_operand {
+            let last = &self.tokens[self.tokens.len()-1];
+            if !last.ttype.is_opening() {
+                return Err(ParseError::MissingOperand { lexeme: last.lexeme.to_string(), position: last.position(), span: last.span() });
+            }
+        }
+
+        while let Some(operator) = self.top() {
+            if operator.ttype.is_opening() {
+                return Err(ParseError::MismatchedLeftParen { position: operator.position(), span: operator.span() });
+            }
+
+            self.explain(format_args!("Applying {} at the end.", operator.lexeme));
+            self.apply_top()?;
+        }
+
+        if self.operands != 1 {
+            return Err(ParseError::CannotEvaluate);
+        }
+
+        self.output.finish()
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.current >= self.tokens.len()
+    }
+
+    fn advance(&mut self) -> &'a Token<'a> {
+        let token = &self.tokens[self.current];
+        self.current += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&'a Token<'a>> {
+        self.tokens.get(self.current)
+    }
+
+    // The token `distance` places before the one just advanced over.
+    fn previous(&self, distance: usize) -> Option<&'a Token<'a>> {
+        if distance >= self.current {
+            return None;
+        }
+
+        Some(&self.tokens[self.current - 1 - distance])
+    }
+
+    fn top(&self) -> Option<&'a Token<'a>> {
+        self.operators.last().cloned()
+    }
+
+    fn check_order(&mut self, token: &'a Token<'a>) -> Result<(), ParseError> {
+        if (token.ttype == TokenType::Number || token.ttype == TokenType::Identifier) && !self.expect_operand {
+            return Err(ParseError::UnexpectedOperand { lexeme: token.lexeme.to_string(), position: token.position(), span: token.span() });
+        }
+
+        let infix = token.ttype == TokenType::Question || token.ttype == TokenType::Colon || token.ttype == TokenType::Comma
+            || (token.ttype.precedence() != 0 && !token.ttype.is_prefix());
+        if infix && self.expect_operand {
+            // A comma after the opening paren or another comma misses the argument before it.
+            if let (TokenType::Comma, Some(previous)) = (token.ttype, self.previous(1)) {
+                return Err(ParseError::MissingOperand { lexeme: previous.lexeme.to_string(), position: previous.position(), span: previous.span() });
+            }
+
+            return Err(ParseError::UnexpectedOperator { lexeme: token.lexeme.to_string(), position: token.position(), span: token.span() });
+        }
+
+        // A closing bracket in the very beginning is just unmatched.
+        if token.ttype.is_closing() && self.expect_operand {
+            if let Some(previous) = self.previous(1) {
+                // An empty group is fine only as a call of a function. Whether it takes no arguments is checked later.
+                let call = previous.ttype.is_opening() && self.previous(2).map(|token| token.ttype) == Some(TokenType::Function);
+                if !call {
+                    return Err(ParseError::MissingOperand { lexeme: previous.lexeme.to_string(), position: previous.position(), span: previous.span() });
+                }
+            }
+        }
+
+        if token.ttype == TokenType::Number || token.ttype == TokenType::Identifier || token.ttype.is_closing() || token.ttype.is_postfix() {
+            self.expect_operand = false;
+        } else if infix {
+            self.expect_operand = true;
+        }
+
+        Ok(())
+    }
+
+    fn operator(&mut self, token: &'a Token<'a>) -> Result<(), ParseError> {
+        // A function call binds tighter than anything else,
+        // but its argument must be set off by parentheses.
+        if token.ttype == TokenType::Function && self.peek().map(|next| next.ttype) != Some(TokenType::LeftParen) {
+            return Err(ParseError::MissingFunctionParen { position: token.position(), span: token.span() });
+        }
+
+        while let Some(top) = self.top().filter(|top| self.table.should_pop(top.ttype, token.ttype)) {
+            if self.table.precedence(top.ttype) > self.table.precedence(token.ttype) {
+                self.explain(format_args!("Applying {} before {} because {} has higher precedence.", top.lexeme, token.lexeme, top.lexeme));
+            } else {
+                self.explain(format_args!("Applying {} before {} because they have the same precedence and group left to right.", top.lexeme, token.lexeme));
+            }
+            self.apply_top()?;
+        }
+
+        // A prefix operator has nothing to do with what is on the stack yet.
+        if let Some(top) = self.top().filter(|top| !top.ttype.is_opening() && top.ttype.precedence() != 0 && !token.ttype.is_prefix()) {
+            if self.table.precedence(top.ttype) < self.table.precedence(token.ttype) {
+                self.explain(format_args!("Keeping {} for later because {} has higher precedence.", top.lexeme, token.lexeme));
+            } else {
+                self.explain(format_args!("Keeping {} for later because {} groups right to left.", top.lexeme, token.lexeme));
+            }
+        }
+
+        self.operators.push(token);
+        self.trace(format_args!("push operator {}", token.lexeme));
+        Ok(())
+    }
+
+    fn opening(&mut self, token: &'a Token<'a>) -> Result<(), ParseError> {
+        // Every open group has its entry in `arguments`.
+        if self.arguments.len() >= self.output.max_depth() {
+            return Err(ParseError::DepthLimitExceeded { limit: self.output.max_depth(), position: token.position(), span: token.span() });
+        }
+
+        let empty = self.peek().is_some_and(|next| next.ttype.is_closing());
+        self.arguments.push(if empty { 0 } else { 1 });
+
+        self.operators.push(token);
+        self.trace(format_args!("push {}", token.lexeme));
+        Ok(())
+    }
+
+    fn question(&mut self, token: &'a Token<'a>) -> Result<(), ParseError> {
+        // The condition is complete. Another conditional on the stack is not applied though,
+        // because the conditional is right-associative: a ? b : c ? d : e is a ? b : (c ? d : e).
+        while let Some(top) = self.top().filter(|top| !top.ttype.is_opening() && top.ttype.precedence() != 0) {
+            self.explain(format_args!("Applying {} before {} because the conditional binds loosest.", top.lexeme, token.lexeme));
+            self.apply_top()?;
+        }
+
+        self.operators.push(token);
+        self.trace(format_args!("push {}", token.lexeme));
+        Ok(())
+    }
+
+    fn colon(&mut self, token: &'a Token<'a>) -> Result<(), ParseError> {
+        // The first branch is complete. Then the colon takes the place of its question mark
+        // and is applied to all three operands later, just like any other operator.
+        while let Some(top) = self.top().filter(|top| !top.ttype.is_opening() && top.ttype != TokenType::Question) {
+            self.explain(format_args!("Applying {} before {} because the conditional binds loosest.", top.lexeme, token.lexeme));
+            self.apply_top()?;
+        }
+
+        if self.top().map(|top| top.ttype) != Some(TokenType::Question) {
+            return Err(ParseError::MismatchedConditional { position: token.position(), span: token.span() });
+        }
+
+        self.operators.pop();
+        self.operators.push(token);
+        self.trace(format_args!("push operator {}", token.lexeme));
+        Ok(())
+    }
+
+    fn comma(&mut self, token: &'a Token<'a>) -> Result<(), ParseError> {
+        // The previous argument is complete.
+        self.apply_group("to complete the argument")?;
+
+        let count = self.operators.len();
+        let calls_function = count >= 2
+            && self.operators[count-1].ttype == TokenType::LeftParen
+            && self.operators[count-2].ttype == TokenType::Function;
+        if !calls_function {
+            return Err(ParseError::UnexpectedComma { position: token.position(), span: token.span() });
+        }
+
+        *self.arguments.last_mut().unwrap() += 1;
+        self.trace(format_args!("next argument"));
+        Ok(())
+    }
+
+    fn closing(&mut self, token: &'a Token<'a>) -> Result<(), ParseError> {
+        let opening = self.operators.iter().rev().find(|operator| operator.ttype.is_opening()).map_or("", |operator| operator.lexeme);
+        self.apply_group(&format!("inside {} {} before anything outside, because the brackets override precedence", opening, token.lexeme))?;
+
+        let opening = match self.operators.pop() {
+            Some(opening) => opening,
+            None => return Err(unopened(token)),
+        };
+
+        if !token.ttype.closes(opening.ttype) {
+            return Err(ParseError::MismatchedGrouping {
+                opening: opening.lexeme.to_string(),
+                closing: token.lexeme.to_string(),
+                position: token.position(),
+                span: token.span(),
+            });
+        }
+
+        self.trace(format_args!("pop {}", opening.lexeme));
+
+        let arity = self.arguments.pop().unwrap();
+
+        // The bars make a call of abs() without the name.
+        if opening.ttype == TokenType::LeftBar {
+            let function = Token { ttype: TokenType::Function, literal: None, lexeme: "abs", ..opening.clone() };
+            self.take_operands(&function, 1)?;
+            self.output.call(&function, 1)?;
+            self.trace(format_args!("call {}", function.lexeme));
+            return Ok(());
+        }
+
+        if let Some(function) = self.top().filter(|top| top.ttype == TokenType::Function) {
+            self.operators.pop();
+
+            if !accepts_arity(function.lexeme, arity) {
+                return Err(ParseError::WrongArgumentCount {
+                    name: function.lexeme.to_string(),
+                    expected: function_arity(function.lexeme).unwrap(),
+                    found: arity,
+                    position: function.position(),
+                    span: function.span(),
+                });
+            }
+
+            self.take_operands(function, arity)?;
+            self.output.call(function, arity)?;
+            self.trace(format_args!("call {}", function.lexeme));
+        }
+
+        Ok(())
+    }
+
+    // Everything down to the innermost opening bracket.
+    fn apply_group(&mut self, reason: &str) -> Result<(), ParseError> {
+        while let Some(top) = self.top().filter(|top| !top.ttype.is_opening()) {
+            self.explain(format_args!("Applying {} {}.", top.lexeme, reason));
+            self.apply_top()?;
+        }
+
+        Ok(())
+    }
+
+    // A question mark still on the stack has never met its colon.
+    fn apply_top(&mut self) -> Result<(), ParseError> {
+        let operator = self.operators.pop().unwrap();
+        if operator.ttype == TokenType::Question {
+            return Err(ParseError::MismatchedConditional { position: operator.position(), span: operator.span() });
+        }
+
+        let arity = if operator.ttype == TokenType::Colon { 3 } else if operator.ttype.is_unary() { 1 } else { 2 };
+        self.take_operands(operator, arity)?;
+
+        self.output.apply(operator)?;
+        self.trace(format_args!("apply {}", operator.lexeme));
+        Ok(())
+    }
+
+    // The operands are replaced by the result.
+    fn take_operands(&mut self, operator: &Token, arity: usize) -> Result<(), ParseError> {
+        if self.operands < arity {
+            return Err(ParseError::MissingOperand { lexeme: operator.lexeme.to_string(), position: operator.position(), span: operator.span() });
+        }
+
+        self.operands = self.operands - arity + 1;
+        Ok(())
+    }
+
+    // The trace for students: why an operator is applied right now rather than later.
+    fn explain(&mut self, reason: fmt::Arguments) {
+        if self.output.explains() {
+            self.output.explain(reason.to_string());
+        }
+    }
+
+    #[cfg(not(feature = "no_std"))]
+    fn trace(&self, action: fmt::Arguments) {
+        if !self.output.trace() {
+            return;
+        }
+
+        let lexemes: Vec<&str> = self.operators.iter().map(|operator| operator.lexeme).collect();
+        eprintln!("{:<24} results: {:<32} operators: [{}]", action.to_string(), self.output.state(), lexemes.join(" "));
+    }
+
+    // There is no stderr to trace to.
+    #[cfg(feature = "no_std")]
+    fn trace(&self, _action: fmt::Arguments) {}
+}
+
+fn shunting_yard<O: Output>(tokens: &[Token], output: O, table: &PrecedenceTable) -> Result<O::Value, ParseError> {
+    Parser::new(tokens, output, table).parse()
+}
+
+// A closing bracket with nothing to close. A stray bar is most likely 2|x|, which reads as 2 followed by a closing bar.
+fn unopened(token: &Token) -> ParseError {
+    if token.ttype == TokenType::RightBar {
+        return ParseError::UnopenedBar { position: token.position(), span: token.span() };
+    }
+
+    ParseError::MismatchedRightParen { position: token.position(), span: token.span() }
+}
+
+// Only the groupings are checked here, so it is possible to go on after an error:
+// an unmatched closing bracket is skipped, a bracket of another kind still closes the group.
+fn check_groupings(tokens: &Vec<Token>) -> Vec<ParseError> {
+    let mut errors = vec![];
+    let mut openings: Vec<&Token> = vec![];
+
+    for token in tokens {
+        if token.ttype.is_opening() {
+            openings.push(token);
+        } else if token.ttype.is_closing() {
+            match openings.pop() {
+                None => errors.push(unopened(token)),
+                Some(opening) if !token.ttype.closes(opening.ttype) => {
+                    errors.push(ParseError::MismatchedGrouping {
+                        opening: opening.lexeme.to_string(),
+                        closing: token.lexeme.to_string(),
+                        position: token.position(),
+                        span: token.span(),
+                    });
+                },
+                Some(_) => {},
+            }
+        }
+    }
+
+    for opening in openings {
+        errors.push(ParseError::MismatchedLeftParen { position: opening.position(), span: opening.span() });
+    }
+
+    errors
+}
+
+fn parse(tokens: &[Token], bindings: &HashMap<String, f64>, settings: Settings, trace: bool) -> Result<f64, ParseError> {
+    parse_with_warnings(tokens, bindings, settings, trace).map(|(result, _)| result)
+}
+
+fn parse_with_warnings(tokens: &[Token], bindings: &HashMap<String, f64>, settings: Settings, trace: bool) -> Result<(f64, Vec<Warning>), ParseError> {
+    let mut evaluation = Evaluation::new(bindings, settings);
+    evaluation.trace = trace;
+    shunting_yard(tokens, Warned { evaluation }, &PrecedenceTable::with_settings(&settings))
+}
+
+fn parse_integer(tokens: &[Token], trace: bool) -> Result<i64, ParseError> {
+    shunting_yard(tokens, IntegerEvaluation { results: vec![], trace }, &PrecedenceTable::new())
+}
+
+fn evaluate(operator: &Token, results: &mut Vec<f64>) -> Result<f64, ParseError> {
+    if operator.ttype.precedence() == 0 {
+        unreachable!("Invalid token type (at pos {}..{}) when an operator expected.", operator.start_at, operator.end_at);
+    }
+
+    let operand = results.pop().unwrap();
+
+    if operator.ttype == TokenType::Bang && (operand < 0.0 || operand.fract() != 0.0) {
+        return Err(ParseError::InvalidFactorial { position: operator.position(), span: operator.span() });
+    }
+
+    if operator.ttype.is_unary() {
+        return Ok(calculate_unary(operator.ttype, operand));
+    }
+
+    if operator.ttype.is_division() && operand == 0.0 {
+        return Err(ParseError::DivisionByZero { position: operator.position(), span: operator.span() });
+    }
+
+    Ok(calculate_binary(operator.ttype, results.pop().unwrap(), operand))
+}
+
+fn evaluate_integer(operator: &Token, results: &mut Vec<i64>) -> Result<i64, ParseError> {
+    let overflow = ParseError::Overflow { position: operator.position(), span: operator.span() };
+    let operand = results.pop().unwrap();
+
+    let result = match operator.ttype {
+        TokenType::UMinus => operand.checked_neg(),
+        TokenType::UPlus => Some(operand),
+        TokenType::Bang => {
+            if operand < 0 {
+                return Err(ParseError::InvalidFactorial { position: operator.position(), span: operator.span() });
+            }
+
+            // 20! is the largest one that fits.
+            match (2..=operand).try_fold(1i64, |product, i| product.checked_mul(i)) {
+                Some(product) => Some(product),
+                None => return Err(ParseError::FactorialOverflow { operand, position: operator.position(), span: operator.span() }),
+            }
+        },
+        TokenType::PercentOf => return Err(ParseError::NotInIntegerMode { lexeme: operator.lexeme.to_string(), position: operator.position(), span: operator.span() }),
+        _ => {
+            let left = results.pop().unwrap();
+            match operator.ttype {
+                TokenType::Plus => left.checked_add(operand),
+                TokenType::Minus => left.checked_sub(operand),
+                TokenType::Star => left.checked_mul(operand),
+                TokenType::Slash | TokenType::DoubleSlash | TokenType::Percent if operand == 0 => {
+                    return Err(ParseError::DivisionByZero { position: operator.position(), span: operator.span() });
+                },
+                // Both truncate toward zero.
+                TokenType::Slash => left.checked_div(operand),
+                TokenType::Percent => left.checked_rem(operand),
+                // Shift the truncated quotient down when the signs differ and something is left over.
+                TokenType::DoubleSlash => left.checked_div(operand).map(|quotient| {
+                    if left % operand != 0 && (left < 0) != (operand < 0) { quotient - 1 } else { quotient }
+                }),
+                TokenType::Caret => {
+                    if operand < 0 {
+                        return Err(ParseError::NegativeExponent { position: operator.position(), span: operator.span() });
+                    }
+
+                    if operand > u32::MAX as i64 { None } else { left.checked_pow(operand as u32) }
+                },
+                TokenType::Less => Some((left < operand) as i64),
+                TokenType::LessEqual => Some((left <= operand) as i64),
+                TokenType::Greater => Some((left > operand) as i64),
+                TokenType::GreaterEqual => Some((left >= operand) as i64),
+                TokenType::EqualEqual => Some((left == operand) as i64),
+                TokenType::BangEqual => Some((left != operand) as i64),
+                _ => unreachable!("Invalid token type (at pos {}..{}) when an operator expected.", operator.start_at, operator.end_at),
+            }
+        },
+    };
+
+    match result {
+        Some(result) => Ok(result),
+        None => Err(overflow),
+    }
+}
+
+fn calculate_unary(op: TokenType, operand: f64) -> f64 {
+    match op {
+        TokenType::UMinus => -operand,
+        TokenType::UPlus => operand,
+        TokenType::Bang => factorial(operand),
+        TokenType::PercentOf => operand / 100.0,
+        _ => unreachable!("Invalid token type {:?} when a unary operator expected.", op),
+    }
+}
+
+fn factorial(n: f64) -> f64 {
+    if n < 0.0 || n.fract() != 0.0 {
+        return f64::NAN;
+    }
+
+    // There is no point to go on when the result is already infinite.
+    let mut result: f64 = 1.0;
+    let mut i = 2.0;
+    while i <= n && result.is_finite() {
+        result *= i;
+        i += 1.0;
+    }
+
+    result
+}
+
+fn calculate_function(name: &str, arguments: &[f64]) -> f64 {
+    match name {
+        "sqrt" => arguments[0].sqrt(),
+        "sin" => arguments[0].sin(),
+        "cos" => arguments[0].cos(),
+        "tan" => arguments[0].tan(),
+        "abs" => arguments[0].abs(),
+        "ln" => arguments[0].ln(),
+        "log10" => arguments[0].log10(),
+        // Halves are rounded away from zero: round(2.5) is 3, round(-2.5) is -3.
+        "round" => arguments[0].round(),
+        "floor" => arguments[0].floor(),
+        "ceil" => arguments[0].ceil(),
+        "trunc" => arguments[0].trunc(),
+        // Unlike Settings::degrees, just for one angle: sin(deg2rad(90)).
+        "deg2rad" => arguments[0] * (consts::PI / 180.0),
+        "rad2deg" => arguments[0] * (180.0 / consts::PI),
+        // log(base, x)
+        "log" => arguments[1].log(arguments[0]),
+        "max" => arguments[1..].iter().fold(arguments[0], |max, argument| max.max(*argument)),
+        "min" => arguments[1..].iter().fold(arguments[0], |min, argument| min.min(*argument)),
+        "sum" => arguments.iter().sum(),
+        "avg" => arguments.iter().sum::<f64>() / arguments.len() as f64,
+        // pow(base, exponent) is the same as base ^ exponent.
+        "pow" => arguments[0].powf(arguments[1]),
+        // clamp(x, lo, hi). f64::clamp() panics on bounds like that.
+        "clamp" if arguments[1].is_nan() || arguments[2].is_nan() || arguments[1] > arguments[2] => f64::NAN,
+        "clamp" => arguments[0].clamp(arguments[1], arguments[2]),
+        "gcd" | "lcm" => match (whole_number(arguments[0]), whole_number(arguments[1])) {
+            (Some(a), Some(b)) if name == "gcd" => gcd(a, b) as f64,
+            // lcm(0, x) is 0. The product may not fit in u64, but it does in f64.
+            (Some(0), Some(_)) | (Some(_), Some(0)) => 0.0,
+            (Some(a), Some(b)) => (a / gcd(a, b)) as f64 * b as f64,
+            _ => f64::NAN,
+        },
+        // There is no seed in a tree.
+        "random" => {
+            let (low, high) = if arguments.is_empty() { (0.0, 1.0) } else { (arguments[0], arguments[1]) };
+            if low.is_nan() || high.is_nan() || low >= high || !(high - low).is_finite() {
+                return f64::NAN;
+            }
+
+            Random::new(None).between(low, high)
+        },
+        _ => unreachable!("Invalid function name: {}.", name),
+    }
+}
+
+// The arguments of gcd() and lcm() come out of float arithmetic, so 0.1 * 30 is taken for 3.
+fn whole_number(number: f64) -> Option<u64> {
+    let rounded = number.round();
+    if !number.is_finite() || (number - rounded).abs() > 1e-9 || !(0.0..=MAX_EXACT_INTEGER).contains(&rounded) {
+        return None;
+    }
+
+    Some(rounded as u64)
+}
+
+// Euclid's algorithm. gcd(0, 0) is 0.
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        return a;
+    }
+
+    gcd(b, a % b)
+}
+
+fn calculate_binary(op: TokenType, left: f64, right: f64) -> f64 {
+    match op {
+        TokenType::Plus => left + right,
+        TokenType::Minus => left - right,
+        TokenType::Star => left * right,
+        TokenType::Slash => left / right,
+        TokenType::DoubleSlash => (left / right).floor(),
+        TokenType::Percent => left % right,
+        TokenType::Caret => left.powf(right),
+        TokenType::Less => truth(left < right),
+        TokenType::LessEqual => truth(left <= right),
+        TokenType::Greater => truth(left > right),
+        TokenType::GreaterEqual => truth(left >= right),
+        TokenType::EqualEqual => truth(left == right),
+        TokenType::BangEqual => truth(left != right),
+        _ => unreachable!("Invalid token type {:?} when a binary operator expected.", op),
+    }
+}
+
+// Comparisons evaluate to numbers as well: 1 is true, 0 is false.
+fn truth(value: bool) -> f64 {
+    if value { 1.0 } else { 0.0 }
+}
+
+// core has only the float functions that need no math library: abs, min, max and the like. With std the rest
+// comes from the system, without it from here. Rounding and sqrt are exact, the others are within a few ulps
+// of std. A power loses about |y * ln(x)| ulps, except the whole ones up to 64 that f64 holds, like 2 ^ 10, which
+// are exact. sin, cos and tan get less precise above 1e6. The tests compare all of them with std, so the module
+// is built for them as well.
+#[cfg(any(test, feature = "no_std"))]
+mod float {
+
+    use super::consts;
+
+    // 2^52: from here on every f64 is a whole number.
+    const WHOLE: f64 = 4503599627370496.0;
+
+    // The constants split in two, so that k * high is exact for the k that come up.
+    const LN_2_HIGH: f64 = 0.6931471803691238;
+    const LN_2_LOW: f64 = 1.9082149292705877e-10;
+    const FRAC_PI_2_HIGH: f64 = 1.5707963267341256;
+    const FRAC_PI_2_LOW: f64 = 6.077100506506192e-11;
+
+    pub fn trunc(x: f64) -> f64 {
+        if x.is_nan() || x.abs() >= WHOLE {
+            return x;
+        }
+
+        (x as i64 as f64).copysign(x)
+    }
+
+    pub fn fract(x: f64) -> f64 {
+        x - trunc(x)
+    }
+
+    pub fn floor(x: f64) -> f64 {
+        let whole = trunc(x);
+        if whole > x { whole - 1.0 } else { whole }
+    }
+
+    pub fn ceil(x: f64) -> f64 {
+        let whole = trunc(x);
+        if whole < x { whole + 1.0 } else { whole }
+    }
+
+    // Halves away from zero. x - trunc(x) is exact, so 0.49999999999999994 is not taken for a half.
+    pub fn round(x: f64) -> f64 {
+        let whole = trunc(x);
+        if (x - whole).abs() >= 0.5 { whole + x.signum() } else { whole }
+    }
+
+    // 2^k for a k in the range of the normal numbers.
+    fn power_of_two(k: i32) -> f64 {
+        f64::from_bits(((k + 1023) as u64) << 52)
+    }
+
+    pub fn sqrt(x: f64) -> f64 {
+        if x < 0.0 {
+            return f64::NAN;
+        }
+        if x == 0.0 || !x.is_finite() {
+            return x;
+        }
+        if x < f64::MIN_POSITIVE {
+            return sqrt(x * power_of_two(108)) * power_of_two(-54);
+        }
+
+        // x = mantissa * 2^exponent with an even exponent, so the root is that of the mantissa times 2^(exponent / 2).
+        let bits = x.to_bits();
+        let (mut mantissa, mut exponent) = (((bits & ((1 << 52) - 1)) | (1 << 52)) as u128, (bits >> 52) as i32 - 1075);
+        if exponent % 2 != 0 {
+            mantissa <<= 1;
+            exponent -= 1;
+        }
+
+        // The root has one bit more than f64 holds. The root of a whole number is never a half, so that bit rounds it.
+        let root = integer_sqrt(mantissa << 54);
+        ((root >> 1) + (root & 1)) as f64 * power_of_two(exponent / 2 - 26)
+    }
+
+    // Newton's method from above goes down to the root and stops there.
+    fn integer_sqrt(n: u128) -> u128 {
+        let mut root = 1 << ((129 - n.leading_zeros()) / 2);
+        loop {
+            let next = (root + n / root) / 2;
+            if next >= root {
+                return root;
+            }
+            root = next;
+        }
+    }
+
+    // e^x = 2^k * e^r with |r| <= ln(2) / 2, where the Taylor series is short.
+    pub fn exp(x: f64) -> f64 {
+        if x.is_nan() {
+            return x;
+        }
+        if x > 709.782712893384 {
+            return f64::INFINITY;
+        }
+        if x < -745.1332191019412 {
+            return 0.0;
+        }
+
+        let k = round(x / consts::LN_2);
+        let r = (x - k * LN_2_HIGH) - k * LN_2_LOW;
+        let mut series = 1.0;
+        for n in (1..=18).rev() {
+            series = 1.0 + series * r / n as f64;
+        }
+
+        // 2^k itself may not be a normal number when the result is.
+        let k = k as i32;
+        series * power_of_two(k / 2) * power_of_two(k - k / 2)
+    }
+
+    // ln(x) = k * ln(2) + ln(m) with m within sqrt(2) of 1, and ln(m) = 2 * atanh((m - 1) / (m + 1)).
+    pub fn ln(x: f64) -> f64 {
+        if x.is_nan() || x < 0.0 {
+            return f64::NAN;
+        }
+        if x == 0.0 {
+            return f64::NEG_INFINITY;
+        }
+        if x == f64::INFINITY {
+            return x;
+        }
+
+        let (x, mut k) = if x < f64::MIN_POSITIVE { (x * power_of_two(54), -54) } else { (x, 0) };
+        k += (x.to_bits() >> 52) as i32 - 1023;
+        let mut m = f64::from_bits((x.to_bits() & ((1 << 52) - 1)) | (1023 << 52));
+        if m > consts::SQRT_2 {
+            m /= 2.0;
+            k += 1;
+        }
+
+        let s = (m - 1.0) / (m + 1.0);
+        let mut series = 0.0;
+        for n in (0..=12).rev() {
+            series = series * s * s + 1.0 / (2 * n + 1) as f64;
+        }
+
+        let k = k as f64;
+        k * LN_2_HIGH + (2.0 * s * series + k * LN_2_LOW)
+    }
+
+    // The powers of ten that f64 holds exactly have their exact logarithm, 3 for 1000 rather than 2.9999999999999996.
+    pub fn log10(x: f64) -> f64 {
+        let log = ln(x) / consts::LN_10;
+        let whole = round(log);
+        if whole.abs() <= 22.0 && powf(10.0, whole) == x { whole } else { log }
+    }
+
+    // The same as f64::log() in std.
+    pub fn log(x: f64, base: f64) -> f64 {
+        ln(x) / ln(base)
+    }
+
+    // x = k * pi / 2 + r with |r| <= pi / 4, then sin(x) is one of sin(r), cos(r), -sin(r) and -cos(r).
+    fn quadrant(x: f64) -> (i64, f64) {
+        let k = round(x * consts::FRAC_2_PI);
+        ((k as i64).rem_euclid(4), (x - k * FRAC_PI_2_HIGH) - k * FRAC_PI_2_LOW)
+    }
+
+    fn sin_series(r: f64) -> f64 {
+        let mut series = 1.0;
+        for n in (1..=10).rev() {
+            series = 1.0 - series * r * r / ((2 * n) * (2 * n + 1)) as f64;
+        }
+
+        r * series
+    }
+
+    fn cos_series(r: f64) -> f64 {
+        let mut series = 1.0;
+        for n in (1..=10).rev() {
+            series = 1.0 - series * r * r / ((2 * n - 1) * (2 * n)) as f64;
+        }
+
+        series
+    }
+
+    pub fn sin(x: f64) -> f64 {
+        if !x.is_finite() {
+            return f64::NAN;
+        }
+
+        match quadrant(x) {
+            (0, r) => sin_series(r),
+            (1, r) => cos_series(r),
+            (2, r) => -sin_series(r),
+            (_, r) => -cos_series(r),
+        }
+    }
+
+    pub fn cos(x: f64) -> f64 {
+        if !x.is_finite() {
+            return f64::NAN;
+        }
+
+        match quadrant(x) {
+            (0, r) => cos_series(r),
+            (1, r) => -sin_series(r),
+            (2, r) => -cos_series(r),
+            (_, r) => sin_series(r),
+        }
+    }
+
+    pub fn tan(x: f64) -> f64 {
+        sin(x) / cos(x)
+    }
+
+    // The special cases are those of std: 0 ^ -1 is inf, (-8) ^ (1 / 3) is NaN, NaN ^ 0 is 1 and so on.
+    pub fn powf(x: f64, y: f64) -> f64 {
+        if y == 0.0 || x == 1.0 {
+            return 1.0;
+        }
+        if x.is_nan() || y.is_nan() {
+            return f64::NAN;
+        }
+
+        let whole = trunc(y) == y;
+        let odd = whole && y.abs() < 2.0 * WHOLE && (y % 2.0).abs() == 1.0;
+        if y.is_infinite() {
+            return if x.abs() == 1.0 {
+                1.0
+            } else if (x.abs() > 1.0) == (y > 0.0) {
+                f64::INFINITY
+            } else {
+                0.0
+            };
+        }
+        if x == 0.0 || x.is_infinite() {
+            let magnitude = if (x == 0.0) == (y < 0.0) { f64::INFINITY } else { 0.0 };
+            return if odd && x.is_sign_negative() { -magnitude } else { magnitude };
+        }
+        if x < 0.0 {
+            if !whole {
+                return f64::NAN;
+            }
+
+            let magnitude = powf(-x, y);
+            return if odd { -magnitude } else { magnitude };
+        }
+
+        // By squaring, so that 2 ^ 10 or 10 ^ 15 is exact.
+        if whole && y.abs() <= 64.0 {
+            let (mut power, mut base, mut n) = (1.0, x, y.abs() as u32);
+            while n > 0 {
+                if n & 1 == 1 {
+                    power *= base;
+                }
+                base *= base;
+                n >>= 1;
+            }
+
+            return if y < 0.0 { 1.0 / power } else { power };
+        }
+
+        exp(y * ln(x))
+    }
+
+    // The methods of f64 in std, for the code that calls them as methods.
+    #[cfg(feature = "no_std")]
+    pub trait Float {
+        fn trunc(self) -> f64;
+        fn fract(self) -> f64;
+        fn floor(self) -> f64;
+        fn ceil(self) -> f64;
+        fn round(self) -> f64;
+        fn sqrt(self) -> f64;
+        fn ln(self) -> f64;
+        fn log10(self) -> f64;
+        fn log(self, base: f64) -> f64;
+        fn sin(self) -> f64;
+        fn cos(self) -> f64;
+        fn tan(self) -> f64;
+        fn powf(self, exponent: f64) -> f64;
+    }
+
+    #[cfg(feature = "no_std")]
+    impl Float for f64 {
+        fn trunc(self) -> f64 { trunc(self) }
+        fn fract(self) -> f64 { fract(self) }
+        fn floor(self) -> f64 { floor(self) }
+        fn ceil(self) -> f64 { ceil(self) }
+        fn round(self) -> f64 { round(self) }
+        fn sqrt(self) -> f64 { sqrt(self) }
+        fn ln(self) -> f64 { ln(self) }
+        fn log10(self) -> f64 { log10(self) }
+        fn log(self, base: f64) -> f64 { log(self, base) }
+        fn sin(self) -> f64 { sin(self) }
+        fn cos(self) -> f64 { cos(self) }
+        fn tan(self) -> f64 { tan(self) }
+        fn powf(self, exponent: f64) -> f64 { powf(self, exponent) }
+    }
+}
+
+pub fn eval(expr: &Expr) -> f64 {
+    match expr {
+        Expr::Number(number) => *number,
+        // A tree knows nothing about the values of its variables.
+        Expr::Variable(_) => f64::NAN,
+        Expr::UnaryOp { op, operand } => calculate_unary(*op, eval(operand)),
+        Expr::BinaryOp { op, left, right } => calculate_binary(*op, eval(left), eval(right)),
+        Expr::Function { name, arguments } => {
+            let arguments: Vec<f64> = arguments.iter().map(eval).collect();
+            calculate_function(name, &arguments)
+        },
+        Expr::Conditional { condition, then, otherwise } => {
+            if eval(condition) != 0.0 { eval(then) } else { eval(otherwise) }
+        },
+    }
+}
+
+// A tree has no positions, so the errors of eval_checked() tell only what went wrong.
+#[derive(Debug, PartialEq)]
+pub enum EvalError {
+    DivisionByZero,
+    Overflow,
+    NotANumber,
+    UnboundVariable { name: String },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::DivisionByZero => write!(f, "Division by zero."),
+            EvalError::Overflow => write!(f, "Overflow."),
+            EvalError::NotANumber => write!(f, "The result is not a number."),
+            EvalError::UnboundVariable { name } => write!(f, "Unknown variable: {}.", name),
+        }
+    }
+}
+
+impl error::Error for EvalError {}
+
+// The same as eval(), but an infinity or a NaN that a node makes out of finite operands is an error.
+// Only the branch of a conditional that is taken is evaluated, so (x > 0 ? 1 / x : 0) is fine.
+pub fn eval_checked(expr: &Expr) -> Result<f64, EvalError> {
+    let (operands, result) = match expr {
+        Expr::Number(number) => return Ok(*number),
+        Expr::Variable(name) => return Err(EvalError::UnboundVariable { name: name.clone() }),
+        Expr::UnaryOp { op, operand } => {
+            let operand = eval_checked(operand)?;
+            (vec![operand], calculate_unary(*op, operand))
+        },
+        Expr::BinaryOp { op, left, right } => {
+            let (left, right) = (eval_checked(left)?, eval_checked(right)?);
+            if right == 0.0 && op.is_division() {
+                return Err(EvalError::DivisionByZero);
+            }
+
+            (vec![left, right], calculate_binary(*op, left, right))
+        },
+        Expr::Function { name, arguments } => {
+            let arguments = arguments.iter().map(eval_checked).collect::<Result<Vec<f64>, EvalError>>()?;
+            let result = calculate_function(name, &arguments);
+            (arguments, result)
+        },
+        Expr::Conditional { condition, then, otherwise } => {
+            return if eval_checked(condition)? != 0.0 { eval_checked(then) } else { eval_checked(otherwise) };
+        },
+    };
+
+    if operands.iter().all(|operand| operand.is_finite()) {
+        if result.is_nan() {
+            return Err(EvalError::NotANumber);
+        }
+
+        if result.is_infinite() {
+            return Err(EvalError::Overflow);
+        }
+    }
+
+    Ok(result)
+}
+
+// The same as eval(), but a subtree that occurs more than once is computed once: in 170! / 169! + 170! / 169!
+// there are two factorials to compute, not four. The tree is still walked as a whole to find the repeats,
+// so it pays off when the repeated subtrees cost more than the walk, like factorials or logarithms.
+pub fn eval_cached(expr: &Expr) -> f64 {
+    SubtreeCache::new().eval(expr)
+}
+
+// Subtrees that are structurally identical are one node here. The nodes are kept between the calls,
+// so trees evaluated one after another share what they have in common. A node is computed when it is needed:
+// only the branch of a conditional that is taken, the same as in eval().
+pub struct SubtreeCache<'a> {
+    ids: HashMap<Node<'a>, usize>,
+    nodes: Vec<Node<'a>>,
+    values: Vec<Option<f64>>,
+}
+
+// The children are the ids of their nodes, so a key is short however deep the subtree is.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Node<'a> {
+    // Bits tell apart 0 and -0, which are equal as numbers.
+    Number(u64),
+    Variable(&'a str),
+    Unary(TokenType, usize),
+    Binary(TokenType, usize, usize),
+    Function(&'a str, Vec<usize>),
+    // random() gives another number every time, so two calls are never one node. The id of the node tells them apart.
+    Impure(&'a str, Vec<usize>, usize),
+    Conditional(usize, usize, usize),
+}
+
+impl<'a> SubtreeCache<'a> {
+
+    pub fn new() -> Self {
+        SubtreeCache { ids: HashMap::new(), nodes: vec![], values: vec![] }
+    }
+
+    pub fn eval(&mut self, expr: &'a Expr) -> f64 {
+        let id = self.insert(expr);
+        self.value(id)
+    }
+
+    // How many distinct subtrees there have been.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    fn insert(&mut self, expr: &'a Expr) -> usize {
+        let node = match expr {
+            Expr::Number(number) => Node::Number(number.to_bits()),
+            Expr::Variable(name) => Node::Variable(name),
+            Expr::UnaryOp { op, operand } => Node::Unary(*op, self.insert(operand)),
+            Expr::BinaryOp { op, left, right } => {
+                let left = self.insert(left);
+                Node::Binary(*op, left, self.insert(right))
+            },
+            Expr::Function { name, arguments } => {
+                let arguments = arguments.iter().map(|argument| self.insert(argument)).collect();
+                if name == "random" {
+                    Node::Impure(name, arguments, self.nodes.len())
+                } else {
+                    Node::Function(name, arguments)
+                }
+            },
+            Expr::Conditional { condition, then, otherwise } => {
+                let condition = self.insert(condition);
+                let then = self.insert(then);
+                Node::Conditional(condition, then, self.insert(otherwise))
+            },
+        };
+
+        if let Some(id) = self.ids.get(&node) {
+            return *id;
+        }
+
+        let id = self.nodes.len();
+        self.ids.insert(node.clone(), id);
+        self.nodes.push(node);
+        self.values.push(None);
+        id
+    }
+
+    fn value(&mut self, id: usize) -> f64 {
+        if let Some(value) = self.values[id] {
+            return value;
+        }
+
+        let value = match self.nodes[id].clone() {
+            Node::Number(bits) => f64::from_bits(bits),
+            Node::Variable(_) => f64::NAN,
+            Node::Unary(op, operand) => {
+                let operand = self.value(operand);
+                calculate_unary(op, operand)
+            },
+            Node::Binary(op, left, right) => {
+                let left = self.value(left);
+                calculate_binary(op, left, self.value(right))
+            },
+            Node::Function(name, arguments) | Node::Impure(name, arguments, _) => {
+                let arguments: Vec<f64> = arguments.iter().map(|argument| self.value(*argument)).collect();
+                calculate_function(name, &arguments)
+            },
+            Node::Conditional(condition, then, otherwise) => {
+                if self.value(condition) != 0.0 { self.value(then) } else { self.value(otherwise) }
+            },
+        };
+
+        self.values[id] = Some(value);
+        value
+    }
+
+}
+
+impl<'a> Default for SubtreeCache<'a> {
+
+    fn default() -> Self {
+        SubtreeCache::new()
+    }
+
+}
+
+// Folds everything that can be computed right away, so `2 + 3 * 4` becomes just 14.
+// With variables around only some parts can be folded, the rest is cleaned up by identities like `x * 1 = x`.
+pub fn simplify(expr: Expr) -> Expr {
+    match expr {
+        Expr::UnaryOp { op, operand } => {
+            let operand = simplify(*operand);
+            if let Expr::Number(number) = operand {
+                return Expr::Number(calculate_unary(op, number));
+            }
+
+            Expr::UnaryOp { op, operand: Box::new(operand) }
+        },
+        Expr::BinaryOp { op, left, right } => {
+            let left = simplify(*left);
+            let right = simplify(*right);
+
+            match (op, &left, &right) {
+                (_, Expr::Number(a), Expr::Number(b)) => return Expr::Number(calculate_binary(op, *a, *b)),
+                (TokenType::Plus, _, Expr::Number(b)) | (TokenType::Minus, _, Expr::Number(b)) if *b == 0.0 => return left,
+                (TokenType::Plus, Expr::Number(a), _) if *a == 0.0 => return right,
+                (TokenType::Star, _, Expr::Number(b)) | (TokenType::Slash, _, Expr::Number(b)) if *b == 1.0 => return left,
+                (TokenType::Star, Expr::Number(a), _) if *a == 1.0 => return right,
+                (TokenType::Star, Expr::Number(a), _) | (TokenType::Star, _, Expr::Number(a)) if *a == 0.0 => return Expr::Number(0.0),
+                _ => {},
+            }
+
+            Expr::BinaryOp { op, left: Box::new(left), right: Box::new(right) }
+        },
+        Expr::Function { name, arguments } => {
+            let arguments: Vec<Expr> = arguments.into_iter().map(simplify).collect();
+
+            let mut numbers = vec![];
+            for argument in &arguments {
+                if let Expr::Number(number) = argument {
+                    numbers.push(*number);
+                }
+            }
+
+            if numbers.len() == arguments.len() {
+                return Expr::Number(calculate_function(&name, &numbers));
+            }
+
+            Expr::Function { name, arguments }
+        },
+        Expr::Conditional { condition, then, otherwise } => {
+            let condition = simplify(*condition);
+            match condition {
+                Expr::Number(number) if number != 0.0 => simplify(*then),
+                Expr::Number(_) => simplify(*otherwise),
+                _ => Expr::Conditional { condition: Box::new(condition), then: Box::new(simplify(*then)), otherwise: Box::new(simplify(*otherwise)) },
+            }
+        },
+        _ => expr,
+    }
+}
+
+// The same tree node by node: 1 + 2 is not 2 + 1. Unlike ==, NaN equals NaN here, but -0 is not 0.
+pub fn structurally_eq(a: &Expr, b: &Expr) -> bool {
+    match (a, b) {
+        (Expr::Number(a), Expr::Number(b)) => a.to_bits() == b.to_bits() || (a.is_nan() && b.is_nan()),
+        (Expr::Variable(a), Expr::Variable(b)) => a == b,
+        (Expr::UnaryOp { op: a, operand: x }, Expr::UnaryOp { op: b, operand: y }) => a == b && structurally_eq(x, y),
+        (Expr::BinaryOp { op: a, left: x, right: y }, Expr::BinaryOp { op: b, left: z, right: w }) => {
+            a == b && structurally_eq(x, z) && structurally_eq(y, w)
+        },
+        (Expr::Function { name: a, arguments: x }, Expr::Function { name: b, arguments: y }) => {
+            a == b && x.len() == y.len() && x.iter().zip(y.iter()).all(|(x, y)| structurally_eq(x, y))
+        },
+        (Expr::Conditional { condition: a, then: x, otherwise: y }, Expr::Conditional { condition: b, then: z, otherwise: w }) => {
+            structurally_eq(a, b) && structurally_eq(x, z) && structurally_eq(y, w)
+        },
+        _ => false,
+    }
+}
+
+// The same tree up to the order of the operands of commutative operators: 1 + 2 is 2 + 1.
+// The grouping still matters, (1 + 2) + 3 is not 1 + (2 + 3): the float addition is not associative.
+pub fn semantically_eq(a: &Expr, b: &Expr) -> bool {
+    canonical(a) == canonical(b)
+}
+
+fn is_commutative(op: TokenType) -> bool {
+    matches!(op, TokenType::Plus | TokenType::Star | TokenType::EqualEqual | TokenType::BangEqual)
+}
+
+// Like to_json(), but the operands of a commutative operator are sorted.
+fn canonical(expr: &Expr) -> String {
+    match expr {
+        Expr::BinaryOp { op, left, right } if is_commutative(*op) => {
+            let mut operands = [canonical(left), canonical(right)];
+            operands.sort();
+            format!("{{\"op\":\"{}\",\"left\":{},\"right\":{}}}", op.symbol(), operands[0], operands[1])
+        },
+        Expr::BinaryOp { op, left, right } => format!("{{\"op\":\"{}\",\"left\":{},\"right\":{}}}", op.symbol(), canonical(left), canonical(right)),
+        Expr::UnaryOp { op, operand } => format!("{{\"op\":\"{}\",\"operand\":{}}}", op.symbol(), canonical(operand)),
+        Expr::Function { name, arguments } => {
+            let arguments: Vec<String> = arguments.iter().map(canonical).collect();
+            format!("{{\"fn\":{},\"args\":[{}]}}", json_string(name), arguments.join(","))
+        },
+        Expr::Conditional { condition, then, otherwise } => {
+            format!("{{\"if\":{},\"then\":{},\"else\":{}}}", canonical(condition), canonical(then), canonical(otherwise))
+        },
+        // Not to_json(): it writes every infinity and NaN as null.
+        Expr::Number(number) => format!("{{\"num\":{:?}}}", number),
+        Expr::Variable(_) => to_json(expr),
+    }
+}
+
+// Parentheses are written only where the tree differs from what precedence and associativity give anyway.
+pub fn to_infix(expr: &Expr) -> String {
+    match expr {
+        // inf and NaN don't parse back, the divisions that make them do.
+        Expr::Number(number) if number.is_nan() => String::from("(0 / 0)"),
+        Expr::Number(number) if number.is_infinite() => format!("({} / 0)", number.signum()),
+        Expr::Number(number) => format!("{}", number),
+        Expr::Variable(name) => name.clone(),
+        Expr::Function { name, arguments } => {
+            let arguments: Vec<String> = arguments.iter().map(to_infix).collect();
+            format!("{}({})", name, arguments.join(", "))
+        },
+        // Only a conditional as the condition needs parentheses, anything else fits in.
+        Expr::Conditional { condition, then, otherwise } => {
+            let condition_parens = infix_precedence(condition) == 0;
+            format!("{} ? {} : {}", parenthesize(condition, condition_parens), to_infix(then), to_infix(otherwise))
+        },
+        Expr::UnaryOp { op, operand } if op.is_postfix() => {
+            format!("{}{}", parenthesize(operand, infix_precedence(operand) < op.precedence()), op.symbol())
+        },
+        Expr::UnaryOp { op, operand } => {
+            format!("{}{}", op.symbol(), parenthesize(operand, infix_precedence(operand) < op.precedence()))
+        },
+        Expr::BinaryOp { op, left, right } => {
+            let precedence = op.precedence();
+            let right_associative = op.associativity() == Associativity::Right;
+
+            let left_parens = infix_precedence(left) < precedence
+                || (infix_precedence(left) == precedence && right_associative);
+
+            // A prefix operator on the right takes its operand no matter what stands before it.
+            let right_parens = !is_prefix_node(right)
+                && (infix_precedence(right) < precedence || (infix_precedence(right) == precedence && !right_associative));
+
+            // A sign after a percent sign would make it a remainder: (50%) - 3 is not 50% - 3.
+            let mut left = parenthesize(left, left_parens);
+            if left.ends_with('%') && (*op == TokenType::Plus || *op == TokenType::Minus) {
+                left = format!("({})", left);
+            }
+
+            format!("{} {} {}", left, op.symbol(), parenthesize(right, right_parens))
+        },
+    }
+}
+
+fn parenthesize(expr: &Expr, parens: bool) -> String {
+    if parens {
+        return format!("({})", to_infix(expr));
+    }
+
+    to_infix(expr)
+}
+
+fn infix_precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::UnaryOp { op, .. } | Expr::BinaryOp { op, .. } => op.precedence(),
+        Expr::Conditional { .. } => TokenType::Colon.precedence(),
+        // A negative number is written with a minus sign, so it behaves like one.
+        _ if is_prefix_node(expr) => TokenType::UMinus.precedence(),
+        _ => TokenType::Function.precedence() + 1,
+    }
+}
+
+fn is_prefix_node(expr: &Expr) -> bool {
+    match expr {
+        Expr::UnaryOp { op, .. } => !op.is_postfix(),
+        Expr::Number(number) => number.is_sign_negative(),
+        _ => false,
+    }
+}
+
+// The division is a fraction, the exponent is a superscript: (1 + 2) / 3 ^ 2 is \frac{1 + 2}{3^{2}}.
+// Parentheses are placed the same way as in to_infix(), except that a fraction or a superscript
+// already groups its parts.
+pub fn to_latex(expr: &Expr) -> String {
+    match expr {
+        Expr::Number(number) => format!("{}", number),
+        Expr::Variable(name) => name.clone(),
+        Expr::Function { name, arguments } => latex_function(name, arguments),
+        Expr::Conditional { condition, then, otherwise } => {
+            format!("\\begin{{cases}} {} & \\text{{if }} {} \\\\ {} & \\text{{otherwise}} \\end{{cases}}",
+                to_latex(then), to_latex(condition), to_latex(otherwise))
+        },
+        Expr::UnaryOp { op, operand } if op.is_postfix() => {
+            format!("{}{}", latex_parenthesize(operand, latex_precedence(operand) < op.precedence()), latex_symbol(*op))
+        },
+        Expr::UnaryOp { op, operand } => {
+            format!("{}{}", latex_symbol(*op), latex_parenthesize(operand, latex_precedence(operand) < op.precedence()))
+        },
+        Expr::BinaryOp { op: TokenType::Slash, left, right } => format!("\\frac{{{}}}{{{}}}", to_latex(left), to_latex(right)),
+        Expr::BinaryOp { op: TokenType::DoubleSlash, left, right } => {
+            format!("\\left\\lfloor \\frac{{{}}}{{{}}} \\right\\rfloor", to_latex(left), to_latex(right))
+        },
+        // A fraction as the base would look like its denominator is raised.
+        Expr::BinaryOp { op: TokenType::Caret, left, right } => {
+            let fraction = matches!(**left, Expr::BinaryOp { op: TokenType::Slash, .. });
+            let parens = fraction || latex_precedence(left) <= TokenType::Caret.precedence();
+
+            format!("{}^{{{}}}", latex_parenthesize(left, parens), to_latex(right))
+        },
+        Expr::BinaryOp { op, left, right } => {
+            let precedence = op.precedence();
+            let left_parens = latex_precedence(left) < precedence;
+            let right_parens = !is_prefix_node(right) && latex_precedence(right) <= precedence;
+
+            format!("{} {} {}", latex_parenthesize(left, left_parens), latex_symbol(*op), latex_parenthesize(right, right_parens))
+        },
+    }
+}
+
+fn latex_parenthesize(expr: &Expr, parens: bool) -> String {
+    if parens {
+        return format!("\\left({}\\right)", to_latex(expr));
+    }
+
+    to_latex(expr)
+}
+
+fn latex_precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::BinaryOp { op: TokenType::Slash, .. } | Expr::BinaryOp { op: TokenType::DoubleSlash, .. } => TokenType::Function.precedence() + 1,
+        _ => infix_precedence(expr),
+    }
+}
+
+fn latex_symbol(op: TokenType) -> String {
+    let symbol = match op {
+        TokenType::Star => "\\cdot",
+        TokenType::Percent => "\\bmod",
+        TokenType::PercentOf => "\\%",
+        TokenType::LessEqual => "\\le",
+        TokenType::GreaterEqual => "\\ge",
+        TokenType::EqualEqual => "=",
+        TokenType::BangEqual => "\\ne",
+        _ => return op.symbol(),
+    };
+
+    symbol.to_string()
+}
+
+fn latex_function(name: &str, arguments: &[Expr]) -> String {
+    let arguments: Vec<String> = arguments.iter().map(to_latex).collect();
+
+    match name {
+        "sqrt" => format!("\\sqrt{{{}}}", arguments[0]),
+        "abs" => format!("\\left|{}\\right|", arguments[0]),
+        "floor" => format!("\\left\\lfloor {} \\right\\rfloor", arguments[0]),
+        "ceil" => format!("\\left\\lceil {} \\right\\rceil", arguments[0]),
+        "log10" => format!("\\log_{{10}}\\left({}\\right)", arguments[0]),
+        "log" => format!("\\log_{{{}}}\\left({}\\right)", arguments[0], arguments[1]),
+        "pow" => format!("\\left({}\\right)^{{{}}}", arguments[0], arguments[1]),
+        // LaTeX knows these names.
+        "sin" | "cos" | "tan" | "ln" | "max" | "min" | "gcd" => format!("\\{}\\left({}\\right)", name, arguments.join(", ")),
+        _ => format!("\\operatorname{{{}}}\\left({}\\right)", name, arguments.join(", ")),
+    }
+}
+
+pub fn parse_str(input: &str) -> Result<Expr, Error> {
+    run(input, &Settings::default(), TreeBuilder { nodes: vec![] }, &PrecedenceTable::new())
+}
+
+// A JSON string with the quotes and the escapes. Also for the --json output of the command line.
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            _ if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+// {"num":1} for numbers, {"var":"x"} for variables, {"op":"+","left":{"num":1},"right":{"num":2}} for binary operators,
+// {"op":"-","operand":{"num":1}} for unary ones and {"fn":"log","args":[{"num":2},{"num":8}]} for functions
+// and {"if":{"num":1},"then":{"num":2},"else":{"num":3}} for conditionals.
+// JSON has no infinities and no NaN, so such a number is null. It is read back as NaN.
+pub fn to_json(expr: &Expr) -> String {
+    match expr {
+        Expr::Number(number) if !number.is_finite() => String::from("{\"num\":null}"),
+        Expr::Number(number) => format!("{{\"num\":{}}}", number),
+        Expr::Variable(name) => format!("{{\"var\":{}}}", json_string(name)),
+        Expr::UnaryOp { op, operand } => format!("{{\"op\":\"{}\",\"operand\":{}}}", op.symbol(), to_json(operand)),
+        Expr::BinaryOp { op, left, right } => format!("{{\"op\":\"{}\",\"left\":{},\"right\":{}}}", op.symbol(), to_json(left), to_json(right)),
+        Expr::Function { name, arguments } => {
+            let arguments: Vec<String> = arguments.iter().map(to_json).collect();
+            format!("{{\"fn\":{},\"args\":[{}]}}", json_string(name), arguments.join(","))
+        },
+        Expr::Conditional { condition, then, otherwise } => {
+            format!("{{\"if\":{},\"then\":{},\"else\":{}}}", to_json(condition), to_json(then), to_json(otherwise))
+        },
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum JsonError {
+    UnexpectedCharacter { ch: char, position: usize },
+    UnexpectedEnd,
+    InvalidNode { position: usize },
+    DepthLimitExceeded { limit: usize, position: usize },
+    UnknownFunction { name: String, position: usize },
+    WrongArgumentCount { name: String, expected: usize, found: usize, position: usize },
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JsonError::UnexpectedCharacter { ch, position } => write!(f, "Unexpected character: {} at position {}.", ch, position),
+            JsonError::UnexpectedEnd => write!(f, "Unexpected end of JSON."),
+            JsonError::InvalidNode { position } => write!(f, "Invalid expression node at position {}.", position),
+            JsonError::DepthLimitExceeded { limit, position } => write!(f, "The nodes are nested deeper than {} levels at position {}.", limit, position),
+            JsonError::UnknownFunction { name, position } => write!(f, "Unknown function: {} at position {}.", name, position),
+            JsonError::WrongArgumentCount { name, expected, found, position } => {
+                let least = if is_variadic(name) { "at least " } else { "" };
+                write!(f, "{} takes {}{} argument(s) but {} given at position {}.", name, least, expected, found, position)
+            },
+        }
+    }
+}
+
+impl error::Error for JsonError {}
+
+pub fn from_json(input: &str) -> Result<Expr, JsonError> {
+    let mut reader = JsonReader { chars: input.chars().collect(), current: 0, depth: 0 };
+
+    let expr = reader.node()?;
+
+    reader.skip_whitespace();
+    if !reader.is_at_end() {
+        return Err(JsonError::UnexpectedCharacter { ch: reader.chars[reader.current], position: reader.current });
+    }
+
+    Ok(expr)
+}
+
+enum JsonValue {
+    Number(f64),
+    Text(String),
+    Node(Expr),
+    Nodes(Vec<Expr>),
+}
+
+// It reads only what to_json produces: objects, arrays of objects, strings and numbers, null among them.
+// The objects and arrays being read are kept on a stack of its own rather than on that of the thread,
+// so a deep tree cannot overflow it. The depth is the number of objects open, at most MAX_DEPTH like in a parsed tree.
+struct JsonReader {
+    chars: Vec<char>,
+    current: usize,
+    depth: usize,
+}
+
+// An object is read up to the value of the key, an array up to the next element.
+enum OpenValue {
+    Node { fields: Vec<(String, JsonValue)>, key: String, position: usize },
+    Nodes(Vec<Expr>),
+}
+
+impl JsonReader {
+
+    fn node(&mut self) -> Result<Expr, JsonError> {
+        let mut open: Vec<OpenValue> = vec![];
+        if self.peek_value()? != '{' {
+            return Err(JsonError::UnexpectedCharacter { ch: self.chars[self.current], position: self.current });
+        }
+
+        loop {
+            let mut value = match self.peek_value()? {
+                '{' => {
+                    open.push(self.open_node()?);
+                    continue;
+                },
+                '[' => {
+                    self.current += 1;
+                    if self.peek_value()? == ']' {
+                        self.current += 1;
+                        JsonValue::Nodes(vec![])
+                    } else {
+                        open.push(OpenValue::Nodes(vec![]));
+                        self.expect_node()?;
+                        continue;
+                    }
+                },
+                '"' => JsonValue::Text(self.string()?),
+                _ => JsonValue::Number(self.number()?),
+            };
+
+            // The value is complete: it goes into the innermost object or array, which may be complete then too.
+            loop {
+                match open.last_mut() {
+                    None => match value {
+                        JsonValue::Node(node) => return Ok(node),
+                        _ => unreachable!("Only an object is read at the top."),
+                    },
+                    Some(OpenValue::Node { fields, key, .. }) => {
+                        fields.push((mem::take(key), value));
+                        if self.comma()? {
+                            *key = self.key()?;
+                            break;
+                        }
+
+                        self.expect('}')?;
+                        self.depth -= 1;
+                        value = match open.pop() {
+                            Some(OpenValue::Node { fields, position, .. }) => JsonValue::Node(JsonReader::make_node(fields, position)?),
+                            _ => unreachable!("The object just completed is gone."),
+                        };
+                    },
+                    Some(OpenValue::Nodes(nodes)) => {
+                        match value {
+                            JsonValue::Node(node) => nodes.push(node),
+                            _ => unreachable!("An element of an array is always an object."),
+                        }
+                        if self.comma()? {
+                            self.expect_node()?;
+                            break;
+                        }
+
+                        self.expect(']')?;
+                        value = match open.pop() {
+                            Some(OpenValue::Nodes(nodes)) => JsonValue::Nodes(nodes),
+                            _ => unreachable!("The array just completed is gone."),
+                        };
+                    },
+                }
+            }
+        }
+    }
+
+    fn open_node(&mut self) -> Result<OpenValue, JsonError> {
+        let position = self.current;
+        if self.depth == MAX_DEPTH {
+            return Err(JsonError::DepthLimitExceeded { limit: MAX_DEPTH, position });
+        }
+
+        self.depth += 1;
+        self.current += 1;
+        Ok(OpenValue::Node { fields: vec![], key: self.key()?, position })
+    }
+
+    fn key(&mut self) -> Result<String, JsonError> {
+        let key = self.string()?;
+        self.expect(':')?;
+        Ok(key)
+    }
+
+    fn expect_node(&mut self) -> Result<(), JsonError> {
+        let c = self.peek_value()?;
+        if c != '{' {
+            return Err(JsonError::UnexpectedCharacter { ch: c, position: self.current });
+        }
+
+        Ok(())
+    }
+
+    fn peek_value(&mut self) -> Result<char, JsonError> {
+        self.skip_whitespace();
+        self.peek()
+    }
+
+    fn comma(&mut self) -> Result<bool, JsonError> {
+        if self.peek_value()? == ',' {
+            self.current += 1;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    fn make_node(fields: Vec<(String, JsonValue)>, position: usize) -> Result<Expr, JsonError> {
+        let mut fields: HashMap<String, JsonValue> = fields.into_iter().collect();
+        let invalid = JsonError::InvalidNode { position };
+        let mut take = |key: &str| fields.remove(key);
+
+        if let Some(JsonValue::Number(number)) = take("num") {
+            return Ok(Expr::Number(number));
+        }
+
+        if let Some(JsonValue::Text(name)) = take("var") {
+            return Ok(Expr::Variable(name));
+        }
+
+        if let (Some(JsonValue::Text(name)), Some(JsonValue::Nodes(arguments))) = (take("fn"), take("args")) {
+            let expected = match function_arity(&name) {
+                Some(expected) => expected,
+                None => return Err(JsonError::UnknownFunction { name, position }),
+            };
+
+            if !accepts_arity(&name, arguments.len()) {
+                return Err(JsonError::WrongArgumentCount { name, expected, found: arguments.len(), position });
+            }
+
+            return Ok(Expr::Function { name, arguments });
+        }
+
+        if let (Some(JsonValue::Node(condition)), Some(JsonValue::Node(then)), Some(JsonValue::Node(otherwise))) = (take("if"), take("then"), take("else")) {
+            return Ok(Expr::Conditional { condition: Box::new(condition), then: Box::new(then), otherwise: Box::new(otherwise) });
+        }
+
+        let symbol = match take("op") {
+            Some(JsonValue::Text(symbol)) => symbol,
+            _ => return Err(invalid),
+        };
+
+        if let Some(JsonValue::Node(operand)) = take("operand") {
+            return match TokenType::from_symbol(&symbol, true) {
+                Some(op) => Ok(Expr::UnaryOp { op, operand: Box::new(operand) }),
+                None => Err(invalid),
+            };
+        }
+
+        if let (Some(JsonValue::Node(left)), Some(JsonValue::Node(right))) = (take("left"), take("right")) {
+            return match TokenType::from_symbol(&symbol, false) {
+                Some(op) => Ok(Expr::BinaryOp { op, left: Box::new(left), right: Box::new(right) }),
+                None => Err(invalid),
+            };
+        }
+
+        Err(invalid)
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+
+        let mut string = String::new();
+        while self.peek()? != '"' {
+            let c = self.chars[self.current];
+            self.current += 1;
+            if c == '\\' {
+                string.push(self.escape()?);
+            } else {
+                string.push(c);
+            }
+        }
+        self.current += 1;
+
+        Ok(string)
+    }
+
+    // What comes after a backslash: \n, \" or \u0007, as json_string() writes them.
+    fn escape(&mut self) -> Result<char, JsonError> {
+        let position = self.current;
+        let c = self.peek()?;
+        self.current += 1;
+
+        let escaped = match c {
+            '"' | '\\' | '/' => c,
+            'b' => '\u{8}',
+            'f' => '\u{c}',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'u' if self.current + 4 <= self.chars.len() => {
+                let digits: String = self.chars[self.current..self.current + 4].iter().collect();
+                self.current += 4;
+                match u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32) {
+                    Some(escaped) => escaped,
+                    None => return Err(JsonError::InvalidNode { position }),
+                }
+            },
+            _ => return Err(JsonError::UnexpectedCharacter { ch: c, position }),
+        };
+
+        Ok(escaped)
+    }
+
+    fn number(&mut self) -> Result<f64, JsonError> {
+        let start = self.current;
+        if self.chars[start..].starts_with(&['n', 'u', 'l', 'l']) {
+            self.current += 4;
+            return Ok(f64::NAN);
+        }
+
+        while !self.is_at_end() && (self.chars[self.current].is_ascii_digit() || "+-.eE".contains(self.chars[self.current])) {
+            self.current += 1;
+        }
+
+        let lexeme: String = self.chars[start..self.current].iter().collect();
+        match lexeme.parse::<f64>() {
+            Ok(number) => Ok(number),
+            Err(_) => Err(JsonError::InvalidNode { position: start }),
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), JsonError> {
+        self.skip_whitespace();
+
+        let c = self.peek()?;
+        if c != expected {
+            return Err(JsonError::UnexpectedCharacter { ch: c, position: self.current });
+        }
+
+        self.current += 1;
+        Ok(())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while !self.is_at_end() && self.chars[self.current].is_whitespace() {
+            self.current += 1;
+        }
+    }
+
+    fn peek(&self) -> Result<char, JsonError> {
+        if self.is_at_end() {
+            return Err(JsonError::UnexpectedEnd);
+        }
+
+        Ok(self.chars[self.current])
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.current >= self.chars.len()
+    }
+
+}
+
+// The entry points that take an input and give what an output makes of it all come here.
+// So whatever the output, the length is checked and the tokens are scanned the way the settings say.
+fn run<O: Output>(input: &str, settings: &Settings, output: O, table: &PrecedenceTable) -> Result<O::Value, Error> {
+    check_length(input, settings.max_input_length)?;
+    let mut scanner = Scanner::with_settings(input, settings);
+    let tokens = scanner.scan_tokens()?;
+    Ok(shunting_yard(tokens, output, table)?)
+}
+
+pub fn tokenize<'a>(input: &'a str) -> Result<Vec<Token<'a>>, ScanError> {
+    check_length(input, None)?;
+    let mut scanner = Scanner::new(input);
+    scanner.scan_tokens()?;
+    Ok(scanner.tokens)
+}
+
+// The source back from its tokens, to check that the scanner has not lost anything.
+// Binary operators are set off by spaces, unary ones stick to their operand: 1 - -2, 5!, max(1, 2).
+// The comments, the original spacing and the implied multiplications are not preserved: 2(3) is 2 * (3).
+pub fn tokens_to_string(tokens: &[Token]) -> String {
+    let mut source = String::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if i > 0 && needs_space(tokens[i-1].ttype, token.ttype) {
+            source.push(' ');
+        }
+        source.push_str(token.lexeme);
+    }
+
+    source
+}
+
+fn needs_space(previous: TokenType, next: TokenType) -> bool {
+    let spaced = |ttype: TokenType| {
+        (ttype.precedence() != 0 && !ttype.is_unary() && ttype != TokenType::Function)
+            || ttype == TokenType::Question || ttype == TokenType::Colon || ttype == TokenType::Equal
+    };
+
+    if spaced(previous) || spaced(next) || previous == TokenType::Comma {
+        return true;
+    }
+
+    if next.is_closing() || next.is_postfix() || next.is_opening() || next == TokenType::Comma {
+        return false;
+    }
+
+    // Two operands in a row are an error, but they are not glued together.
+    !previous.is_opening() && !previous.is_prefix()
+}
+
+// Integer division truncates, and non-integer literals are rejected by the scanner.
+pub fn evaluate_int(input: &str) -> Result<i64, Error> {
+    check_length(input, None)?;
+    let mut scanner = Scanner::new(input);
+    scanner.integer = true;
+    let tokens = scanner.scan_tokens()?;
+    Ok(parse_integer(tokens, false)?)
+}
+
+// Scans the current line and checks its groupings, collecting all the errors.
+// If there are none, the tokens of the scanner are ready to be parsed.
+fn scan_checked(scanner: &mut Scanner) -> Vec<Error> {
+    let mut errors: Vec<Error> = scanner.scan_tokens_recovering().into_iter().map(Error::from).collect();
+    errors.extend(check_groupings(&scanner.tokens).into_iter().map(Error::from));
+    errors
+}
+
+// Reports as many errors as possible at once instead of stopping at the first one.
+pub fn evaluate_collecting_errors(input: &str) -> Result<f64, Vec<Error>> {
+    if let Err(error) = check_length(input, None) {
+        return Err(vec![Error::from(error)]);
+    }
+
+    let mut scanner = Scanner::new(input);
+
+    let errors = scan_checked(&mut scanner);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    parse(&scanner.tokens, &HashMap::new(), Settings::default(), false).map_err(|error| vec![Error::from(error)])
+}
+
+// Every non-blank line is evaluated on its own. The results go along with the line numbers, starting at 1.
+pub fn evaluate_all(input: &str) -> Vec<(usize, Result<f64, Error>)> {
+    if let Err(error) = check_length(input, None) {
+        return vec![(1, Err(Error::from(error)))];
+    }
+
+    let mut scanner = Scanner::new(input);
+    let mut results = vec![];
+
+    while !scanner.is_at_end() {
+        let line = scanner.line;
+
+        let result = match scanner.scan_tokens() {
+            Ok(tokens) if tokens.is_empty() => None,
+            Ok(tokens) => Some(parse(tokens, &HashMap::new(), Settings::default(), false).map_err(Error::from)),
+            Err(error) => Some(Err(Error::from(error))),
+        };
+
+        if let Some(result) = result {
+            results.push((line, result));
+        }
+
+        scanner.next_line();
+    }
+
+    results
+}
+
+// Checks the syntax only: a division by zero or an unknown variable is fine here.
+pub fn validate(input: &str) -> Result<(), Error> {
+    run(input, &Settings::default(), Validation, &PrecedenceTable::new())
+}
+
+// An expression that is scanned and parsed once, but can be evaluated many times with different variables:
+//
+//     let program = compile("x ^ 2 + 1")?;
+//     for x in 0..10 {
+//         bindings.insert(String::from("x"), x as f64);
+//         program.run(&bindings)?;
+//     }
+//
+// The errors of the syntax are reported by compile(), the errors of the evaluation by run().
+#[derive(Debug, Clone)]
+pub struct Program {
+    instructions: Vec<Instruction>,
+}
+
+impl Program {
+
+    pub fn run(&self, bindings: &HashMap<String, f64>) -> Result<f64, Error> {
+        let mut output = Evaluation::new(bindings, Settings::default());
+
+        for instruction in &self.instructions {
+            match instruction {
+                Instruction::Push(number) => output.results.push(Ok(*number)),
+                Instruction::Load(name) => output.variable(&name.token())?,
+                Instruction::Apply(operator) => output.apply(&operator.token())?,
+                Instruction::Call(function, arity) => output.call(&function.token(), *arity)?,
+            }
+        }
+
+        Ok(output.finish()?)
+    }
+}
+
+pub fn compile(input: &str) -> Result<Program, Error> {
+    run(input, &Settings::default(), Compiler { instructions: vec![] }, &PrecedenceTable::new())
+}
+
+pub fn explain(input: &str) -> Result<Vec<String>, Error> {
+    run(input, &Settings::default(), Explanation { reasons: vec![] }, &PrecedenceTable::new())
+}
+
+pub fn to_rpn(input: &str) -> Result<Vec<String>, Error> {
+    run(input, &Settings::default(), RpnWriter { lexemes: vec![] }, &PrecedenceTable::new())
+}
+
+pub fn evaluate_str(input: &str) -> Result<f64, Error> {
+    evaluate_with(input, &HashMap::new())
+}
+
+pub fn evaluate_with(input: &str, bindings: &HashMap<String, f64>) -> Result<f64, Error> {
+    let settings = Settings::default();
+    run(input, &settings, Evaluation::new(bindings, settings), &PrecedenceTable::with_settings(&settings))
+}
+
+// The result along with the steps that lead to it. See Steps.
+pub fn evaluate_steps(input: &str) -> Result<(f64, Vec<String>), Error> {
+    let settings = Settings::default();
+    let bindings = HashMap::new();
+    let steps = Steps { evaluation: Evaluation::new(&bindings, settings), steps: vec![] };
+    run(input, &settings, steps, &PrecedenceTable::with_settings(&settings))
+}
+
+pub fn evaluate_with_settings(input: &str, settings: &Settings) -> Result<f64, Error> {
+    let bindings = HashMap::new();
+    run(input, settings, Evaluation::new(&bindings, *settings), &PrecedenceTable::with_settings(settings))
+}
+
+// Like evaluate_with_settings, but also tells what may have gone differently than meant. See Warning.
+pub fn evaluate_with_warnings(input: &str, settings: &Settings) -> Result<(f64, Vec<Warning>), Error> {
+    let bindings = HashMap::new();
+    run(input, settings, Warned { evaluation: Evaluation::new(&bindings, *settings) }, &PrecedenceTable::with_settings(settings))
+}
+
+pub fn evaluate_with_decimal_comma(input: &str) -> Result<f64, Error> {
+    evaluate_with_settings(input, &Settings { decimal_comma: true, ..Settings::default() })
+}
+
+pub fn evaluate_exact(input: &str) -> Result<f64, Error> {
+    evaluate_with_settings(input, &Settings { exact: true, ..Settings::default() })
+}
+
+// The table replaces the one the settings would make, so Settings::calculator and Settings::tight_minus do not apply.
+pub fn evaluate_with_table(input: &str, table: &PrecedenceTable) -> Result<f64, Error> {
+    let settings = Settings::default();
+    let bindings = HashMap::new();
+    run(input, &settings, Evaluation::new(&bindings, settings), table)
+}
+
+// name = expression. The name must be a variable: constants and functions are not scanned as identifiers.
+fn assignment<'t, 'a>(tokens: &'t [Token<'a>]) -> Option<(&'a str, &'t [Token<'a>])> {
+    if tokens.len() >= 2 && tokens[0].ttype == TokenType::Identifier && tokens[1].ttype == TokenType::Equal {
+        return Some((tokens[0].lexeme, &tokens[2..]));
+    }
+
+    None
+}
+
+// Lines evaluated one after another. A line may assign a variable, then the following ones can use it.
+// The last result is also kept as `ans`:
+//
+//     let mut session = Session::new();
+//     session.evaluate("x = 3 + 2");
+//     session.evaluate("x * 4"); // Ok(20.0)
+//     session.evaluate("ans + 1"); // Ok(21.0)
+pub struct Session {
+    variables: HashMap<String, f64>,
+}
+
+impl Session {
+
+    pub fn new() -> Self {
+        Session { variables: HashMap::new() }
+    }
+
+    pub fn variable(&self, name: &str) -> Option<f64> {
+        self.variables.get(name).cloned()
+    }
+
+    // An assignment gives the assigned value.
+    pub fn evaluate(&mut self, line: &str) -> Result<f64, Error> {
+        check_length(line, None)?;
+        let mut scanner = Scanner::new(line);
+        let tokens = scanner.scan_tokens()?;
+
+        let value = match assignment(tokens) {
+            Some((name, expression)) => {
+                let value = parse(expression, &self.variables, Settings::default(), false)?;
+                self.variables.insert(name.to_string(), value);
+                value
+            },
+            None => parse(tokens, &self.variables, Settings::default(), false)?,
+        };
+
+        self.variables.insert(ANS.to_string(), value);
+        Ok(value)
+    }
+}
+
+impl Default for Session {
+
+    fn default() -> Self {
+        Session::new()
+    }
+
+}
+
+// For the browser. There is no Cargo manifest, so the feature is enabled by hand, see the README.
+#[cfg(feature = "wasm")]
+mod wasm {
+    use wasm_bindgen::prelude::*;
+
+    // An error is thrown as a JS exception with the same message as in the console.
+    #[wasm_bindgen]
+    pub fn evaluate(input: &str) -> Result<f64, JsValue> {
+        super::evaluate_str(input).map_err(|error| JsValue::from_str(&error.to_string()))
+    }
+
+    // wasm-pack test --node -- --features wasm
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use wasm_bindgen_test::*;
+
+        #[wasm_bindgen_test]
+        fn evaluates() {
+            assert_eq!(evaluate("1 + 2 * 3").unwrap(), 7.0);
+            assert_eq!(evaluate("max(1, -2) ^ 2").unwrap(), 1.0);
+        }
+
+        #[wasm_bindgen_test]
+        fn throws_the_message() {
+            assert_eq!(evaluate("1 / 0").unwrap_err().as_string().unwrap(), "Division by zero at line 1, column 3.");
+            assert_eq!(evaluate("2 +").unwrap_err().as_string().unwrap(), "Expected an operand after + at line 1, column 3.");
+        }
+    }
+}
+
+// How a float result is printed: 1234500, 1.2345e6 or 1.2345e6 with the exponent a multiple of three.
+#[cfg(not(feature = "no_std"))]
+#[derive(Clone, Copy, PartialEq)]
+enum Notation {
+    Plain,
+    Scientific,
+    Engineering,
+}
+
+#[cfg(not(feature = "no_std"))]
+struct Options {
+    repl: bool,
+    trace: bool,
+    tokens: bool,
+    integer: bool,
+    settings: Settings,
+    precision: Option<usize>,
+    notation: Notation,
+    grouped: bool,
+    json: bool,
+    explain: bool,
+    expression: Option<String>,
+}
+
+#[cfg(not(feature = "no_std"))]
+fn parse_args() -> Options {
+    let mut options = Options {
+        repl: false,
+        trace: false,
+        tokens: false,
+        integer: false,
+        settings: Settings::default(),
+        precision: None,
+        notation: Notation::Plain,
+        grouped: false,
+        json: false,
+        explain: false,
+        expression: None,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--repl" => options.repl = true,
+            "--trace" => options.trace = true,
+            "--tokens" => options.tokens = true,
+            "--int" => options.integer = true,
+            "--finite" => options.settings.finite = true,
+            "--allow-nan" => options.settings.allow_nan = true,
+            "--degrees" => options.settings.degrees = true,
+            "--calculator" => options.settings.calculator = true,
+            "--tight-minus" => options.settings.tight_minus = true,
+            "--scientific" => options.notation = Notation::Scientific,
+            "--engineering" => options.notation = Notation::Engineering,
+            "--grouped" => options.grouped = true,
+            "--decimal-comma" => options.settings.decimal_comma = true,
+            "--json" => options.json = true,
+            "--exact" => options.settings.exact = true,
+            "--explain" => options.explain = true,
+            "--precision" => {
+                match args.next().and_then(|value| value.parse::<usize>().ok()) {
+                    Some(precision) => options.precision = Some(precision),
+                    None => {
+                        eprintln!("--precision expects a number of decimal places.");
+                        process::exit(64);
+                    },
+                }
+            },
+            _ => options.expression = Some(arg),
+        }
+    }
+
+    options
+}
+
+// Without a precision the value is printed as is.
+#[cfg(not(feature = "no_std"))]
+fn format_result(result: f64, options: &Options) -> String {
+    let text = match (options.notation, options.precision) {
+        (Notation::Engineering, precision) if result.is_finite() => engineering(result, precision),
+        (Notation::Scientific, Some(precision)) => format!("{:.*e}", precision, result),
+        (Notation::Scientific, None) => format!("{:e}", result),
+        (_, Some(precision)) => fixed(result, precision),
+        (_, None) => format!("{}", result),
+    };
+
+    format_separators(text, options)
+}
+
+// A whole number is printed without a fraction even with a precision: 6, not 6.00.
+// A number that only rounds to a whole one keeps its zeros, 5.999 is 6.00 with two places.
+#[cfg(not(feature = "no_std"))]
+fn fixed(result: f64, precision: usize) -> String {
+    if result.fract() == 0.0 {
+        return format!("{}", result);
+    }
+
+    format!("{:.*}", precision, result)
+}
+
+// 12345 is 12.345e3 and 0.0012 is 1.2e-3. The point of the scientific form moves to the right
+// until the exponent is a multiple of three. The precision is the number of digits after the point still.
+#[cfg(not(feature = "no_std"))]
+fn engineering(number: f64, precision: Option<usize>) -> String {
+    let exponent = |scientific: &str| scientific[scientific.find('e').unwrap() + 1..].parse::<i32>().unwrap();
+
+    let mut scientific = format!("{:e}", number);
+    if let Some(precision) = precision {
+        // Rounding may carry over into the next power of ten: 999.96 with two digits is 1.00e3, not 999.96e0.
+        let shift = exponent(&format!("{:.*e}", precision, number)).rem_euclid(3) as usize;
+        scientific = format!("{:.*e}", precision + shift, number);
+    }
+
+    let power = exponent(&scientific);
+    let shift = power.rem_euclid(3) as usize;
+
+    let mantissa = &scientific[..scientific.find('e').unwrap()];
+    let (sign, mantissa) = match mantissa.strip_prefix('-') { Some(unsigned) => ("-", unsigned), None => ("", mantissa) };
+    let mut digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    while digits.len() < shift + 1 {
+        digits.push('0');
+    }
+
+    let (integer, fraction) = digits.split_at(shift + 1);
+    if fraction.is_empty() {
+        return format!("{}{}e{}", sign, integer, power - shift as i32);
+    }
+
+    format!("{}{}.{}e{}", sign, integer, fraction, power - shift as i32)
+}
+
+#[cfg(not(feature = "no_std"))]
+fn format_integer(result: i64, options: &Options) -> String {
+    format_separators(result.to_string(), options)
+}
+
+#[cfg(not(feature = "no_std"))]
+fn format_separators(text: String, options: &Options) -> String {
+    let text = if options.grouped { group_thousands(&text) } else { text };
+
+    // The point and the comma swap places: 1.234,5
+    if options.settings.decimal_comma {
+        return text.chars().map(|c| match c { '.' => ',', ',' => '.', _ => c }).collect();
+    }
+
+    text
+}
+
+// 1234567.89 is 1,234,567.89. Only the integer part is grouped, so the precision is kept as it is.
+#[cfg(not(feature = "no_std"))]
+fn group_thousands(number: &str) -> String {
+    let (sign, unsigned) = match number.strip_prefix('-') { Some(unsigned) => ("-", unsigned), None => ("", number) };
+    let digits = unsigned.find(|c: char| !c.is_ascii_digit()).unwrap_or(unsigned.len());
+    let (integer, rest) = unsigned.split_at(digits);
+
+    let mut grouped = String::new();
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+
+    format!("{}{}{}", sign, grouped, rest)
+}
+
+// With --json the errors go to stdout as well, one object per line: {"error": "...", "position": 4}.
+// The position is the offset of the first char of the error in the source, or null if there is none.
+#[cfg(not(feature = "no_std"))]
+fn report(errors: Vec<Error>, options: &Options) {
+    for error in errors {
+        if options.json {
+            let position = error.span().map_or(String::from("null"), |span| span.start.to_string());
+            println!("{{\"error\": {}, \"position\": {}}}", json_string(&error.to_string()), position);
+        } else {
+            eprintln!("{}", error);
+        }
+    }
+}
+
+// JSON has no NaN or infinity.
+#[cfg(not(feature = "no_std"))]
+fn json_number(number: f64) -> String {
+    if !number.is_finite() {
+        return String::from("null");
+    }
+
+    format!("{:?}", number)
+}
+
+#[cfg(not(feature = "no_std"))]
+fn get_source(options: &Options) -> String {
+    if let Some(expression) = &options.expression {
+        return expression.clone();
+    }
+
+    // Without the file the expression is piped in: echo "1 + 2" | shunting_yard
+    match fs::read_to_string("expression.txt") {
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound => {
+            let mut data = String::new();
+            if let Err(why) = io::stdin().read_to_string(&mut data) {
+                panic!("Couldn't read stdin: {}", why);
+            }
+
+            data
+        },
+        Err(why) => panic!("Couldn't read: {}", why),
+        Ok(data) => data,
+    }
+}
+
+// Scans and evaluates the current line. None means the line is blank.
+// All the errors of the line are reported at once, if possible.
+// An assigned variable goes to `variables` and can be used on the following lines, and so does `ans`.
+#[cfg(not(feature = "no_std"))]
+fn evaluate_line(scanner: &mut Scanner, options: &Options, variables: &mut HashMap<String, f64>) -> Option<Result<String, Vec<Error>>> {
+    scanner.integer = options.integer;
+
+    let errors = scan_checked(scanner);
+    if !errors.is_empty() {
+        return Some(Err(errors));
+    }
+
+    let tokens = &scanner.tokens;
+    if tokens.is_empty() {
+        return None;
+    }
+
+    if options.tokens {
+        for token in tokens {
+            println!("{}", token);
+        }
+    }
+
+    let (name, expression) = match assignment(tokens) {
+        Some((name, expression)) => (Some(name), expression),
+        None => (None, &tokens[..]),
+    };
+
+    // Only the order of the operators, without evaluating anything.
+    if options.explain {
+        let table = PrecedenceTable::with_settings(&options.settings);
+        let explanation = shunting_yard(expression, Explanation { reasons: vec![] }, &table).map(|reasons| {
+            if reasons.is_empty() {
+                return String::from("There are no operators to put in order.");
+            }
+
+            reasons.join("\n")
+        });
+        return Some(explanation.map_err(|error| vec![Error::from(error)]));
+    }
+
+    // The result both as it is printed and as a JSON number.
+    let result = if options.integer && name.is_some() {
+        Err(ParseError::NotInIntegerMode { lexeme: tokens[1].lexeme.to_string(), position: tokens[1].position(), span: tokens[1].span() })
+    } else if options.integer {
+        parse_integer(expression, options.trace).map(|result| (format_integer(result, options), result.to_string()))
+    } else {
+        parse_with_warnings(expression, variables, options.settings, options.trace).map(|(result, warnings)| {
+            for warning in warnings {
+                eprintln!("Warning: {}", warning);
+            }
+
+            if let Some(name) = name {
+                variables.insert(name.to_string(), result);
+            }
+            variables.insert(ANS.to_string(), result);
+
+            (format_result(result, options), json_number(result))
+        })
+    };
+
+    if options.json {
+        let input = scanner.source.lines().nth(tokens[0].line - 1).unwrap_or("").trim();
+        return Some(result.map(|(_, number)| format!(
+            "{{\"input\": {}, \"result\": {}, \"tokens\": {}}}", json_string(input), number, tokens.len()
+        )).map_err(|error| vec![Error::from(error)]));
+    }
+
+    let result = result.map(|(result, _)| result);
+    let result = match name {
+        Some(name) => result.map(|result| format!("{} = {}", name, result)),
+        None => result.map(|result| format!("Result: {}", result)),
+    };
+
+    Some(result.map_err(|error| vec![Error::from(error)]))
+}
+
+// Blank lines are skipped. An error on one line
+// does not stop the following lines from being evaluated.
+#[cfg(not(feature = "no_std"))]
+fn evaluate_lines(source: &str, options: &Options) -> bool {
+    if let Err(error) = check_length(source, options.settings.max_input_length) {
+        report(vec![Error::from(error)], options);
+        return false;
+    }
+
+    let mut scanner = Scanner::with_settings(source, &options.settings);
+    let mut variables = HashMap::new();
+    let mut succeeded = true;
+    let mut evaluated = false;
+
+    while !scanner.is_at_end() {
+        let result = evaluate_line(&mut scanner, options, &mut variables);
+        evaluated = evaluated || result.is_some();
+
+        match result {
+            None => {},
+            Some(Ok(result)) => println!("{}", result),
+            Some(Err(errors)) => {
+                report(errors, options);
+                succeeded = false;
+            },
+        }
+
+        scanner.next_line();
+    }
+
+    // Blank lines are skipped, but there must be at least one expression.
+    if !evaluated {
+        report(vec![Error::from(ParseError::EmptyExpression)], options);
+        return false;
+    }
+
+    succeeded
+}
+
+// Every line is a separate expression. The scanner stops at "\n" anyway,
+// so a line can be scanned as it is.
+#[cfg(not(feature = "no_std"))]
+fn repl(options: &Options) {
+    let stdin = io::stdin();
+    let mut line = String::new();
+    let mut variables = HashMap::new();
+
+    loop {
+        print!("> ");
+        io::stdout().flush().unwrap();
+
+        line.clear();
+        match stdin.lock().read_line(&mut line) {
+            Err(why) => panic!("Couldn't read: {}", why),
+            Ok(0) => {
+                println!();
+                break;
+            },
+            Ok(_) => {},
+        }
+
+        let mut scanner = Scanner::with_settings(&line, &options.settings);
+        match evaluate_line(&mut scanner, options, &mut variables) {
+            None => {},
+            Some(Ok(result)) => println!("{}", result),
+            Some(Err(errors)) => report(errors, options),
+        }
+    }
+}
+
+#[cfg(not(feature = "no_std"))]
+fn main() {
+    let options = parse_args();
+
+    if options.repl {
+        repl(&options);
+        return;
+    }
+
+    let source: String = get_source(&options);
+
+    if !evaluate_lines(&source, &options) {
+        process::exit(65);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A linear congruential generator is enough to make up trees, and a failure can be replayed from the seed.
+    struct Generator {
+        state: u64,
+    }
+
+    impl Generator {
+
+        fn below(&mut self, bound: usize) -> usize {
+            self.state = self.state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((self.state >> 33) % bound as u64) as usize
+        }
+
+        fn leaf(&mut self) -> Expr {
+            match self.below(4) {
+                0 => Expr::Variable(["x", "y"][self.below(2)].to_string()),
+                1 => Expr::Number(self.below(400) as f64 / 8.0),
+                _ => Expr::Number(self.below(20) as f64),
+            }
+        }
+
+        fn expr(&mut self, depth: usize) -> Expr {
+            if depth == 0 {
+                return self.leaf();
+            }
+
+            match self.below(10) {
+                0 => self.leaf(),
+                1..=2 => {
+                    let op = [TokenType::UMinus, TokenType::UPlus, TokenType::Bang, TokenType::PercentOf][self.below(4)];
+                    Expr::UnaryOp { op, operand: Box::new(self.expr(depth - 1)) }
+                },
+                3..=6 => {
+                    let operators = [
+                        TokenType::Plus, TokenType::Minus, TokenType::Star, TokenType::Slash, TokenType::DoubleSlash,
+                        TokenType::Percent, TokenType::Caret, TokenType::Less, TokenType::LessEqual, TokenType::Greater,
+                        TokenType::GreaterEqual, TokenType::EqualEqual, TokenType::BangEqual,
+                    ];
+                    let op = operators[self.below(operators.len())];
+                    Expr::BinaryOp { op, left: Box::new(self.expr(depth - 1)), right: Box::new(self.expr(depth - 1)) }
+                },
+                7..=8 => {
+                    let (name, arity) = FUNCTIONS[self.below(FUNCTIONS.len())];
+                    let arity = if is_variadic(name) { arity + self.below(3) } else { arity };
+                    let arguments = (0..arity).map(|_| self.expr(depth - 1)).collect();
+                    Expr::Function { name: name.to_string(), arguments }
+                },
+                _ => Expr::Conditional {
+                    condition: Box::new(self.expr(depth - 1)),
+                    then: Box::new(self.expr(depth - 1)),
+                    otherwise: Box::new(self.expr(depth - 1)),
+                },
+            }
+        }
+
+        // An expression written out with as few parentheses as it can have, so the precedence and the
+        // associativity of the parser decide what it means. The Reference below reads it on its own.
+        fn text(&mut self, depth: usize) -> String {
+            if depth == 0 || self.below(8) == 0 {
+                return match self.below(4) {
+                    0 => format!("{}", self.below(400) as f64 / 8.0),
+                    1 => format!("{}!", self.below(8)),
+                    _ => format!("{}", self.below(20)),
+                };
+            }
+
+            match self.below(10) {
+                0..=1 => format!("{} {}", ["-", "+"][self.below(2)], self.text(depth - 1)),
+                2 => format!("({})", self.text(depth - 1)),
+                3..=7 => {
+                    let op = ["+", "-", "*", "/", "//", "%", "^", "^"][self.below(8)];
+                    format!("{} {} {}", self.text(depth - 1), op, self.text(depth - 1))
+                },
+                _ => match self.below(3) {
+                    0 => format!("abs({})", self.text(depth - 1)),
+                    name => format!("{}({}, {})", ["max", "min"][name - 1], self.text(depth - 1), self.text(depth - 1)),
+                },
+            }
+        }
+    }
+
+    fn same_value(a: f64, b: f64) -> bool {
+        a.to_bits() == b.to_bits() || (a.is_nan() && b.is_nan())
+    }
+
+    // The options of a plain run without any flags.
+    fn options() -> Options {
+        Options { repl: false, trace: false, tokens: false, integer: false, settings: Settings::default(), precision: None, notation: Notation::Plain, grouped: false, json: false, explain: false, expression: None }
+    }
+
+    #[test]
+    fn printed_trees_parse_back() {
+        for seed in 0..2000 {
+            let mut generator = Generator { state: seed };
+            let tree = generator.expr(5);
+            let infix = to_infix(&tree);
+            let parsed = match parse_str(&infix) {
+                Ok(parsed) => parsed,
+                Err(error) => panic!("seed {}: {} does not parse: {}", seed, infix, error),
+            };
+
+            assert!(structurally_eq(&parsed, &tree), "seed {}: {} is read as {}", seed, infix, to_infix(&parsed));
+            assert!(same_value(eval(&parsed), eval(&tree)), "seed {}: {}", seed, infix);
+        }
+    }
+
+    // A recursive descent evaluator of the usual grammar, which knows nothing of PrecedenceTable:
+    //     sum = product (("+" | "-") product)*
+    //     product = sign (("*" | "/" | "//" | "%") sign)*
+    //     sign = ("-" | "+") sign | power
+    //     power = factorial ("^" sign)?
+    //     factorial = primary "!"?
+    // A zero divisor is an error, and so is a result that is not a number while the operands are finite.
+    // A NaN of the infinities, as 10^400 - 10^400 is, goes through. Either way the input is read to the end.
+    struct Reference<'a> {
+        input: &'a str,
+        failed: bool,
+    }
+
+    impl<'a> Reference<'a> {
+
+        fn eat(&mut self, text: &str) -> bool {
+            self.input = self.input.trim_start();
+            if self.input.starts_with(text) {
+                self.input = &self.input[text.len()..];
+                true
+            } else {
+                false
+            }
+        }
+
+        fn expect(&mut self, text: &str) {
+            assert!(self.eat(text), "{} is expected at {}", text, self.input);
+        }
+
+        fn checked(&mut self, operands: [f64; 2], value: f64) -> f64 {
+            self.failed |= operands.iter().all(|operand| operand.is_finite()) && value.is_nan();
+            value
+        }
+
+        fn sum(&mut self) -> f64 {
+            let mut value = self.product();
+            loop {
+                let plus = if self.eat("+") {
+                    true
+                } else if self.eat("-") {
+                    false
+                } else {
+                    return value;
+                };
+
+                let operand = self.product();
+                value = self.checked([value, operand], if plus { value + operand } else { value - operand });
+            }
+        }
+
+        fn product(&mut self) -> f64 {
+            let mut value = self.sign();
+            loop {
+                // The longer operator first, or // would be read as two divisions.
+                let operator = match ["//", "*", "/", "%"].iter().find(|operator| self.eat(operator)) {
+                    Some(operator) => *operator,
+                    None => return value,
+                };
+
+                let operand = self.sign();
+                self.failed |= operator != "*" && operand == 0.0;
+                let result = match operator {
+                    "*" => value * operand,
+                    "/" => value / operand,
+                    "//" => (value / operand).floor(),
+                    _ => value % operand,
+                };
+                value = self.checked([value, operand], result);
+            }
+        }
+
+        fn sign(&mut self) -> f64 {
+            if self.eat("-") {
+                -self.sign()
+            } else if self.eat("+") {
+                self.sign()
+            } else {
+                self.power()
+            }
+        }
+
+        fn power(&mut self) -> f64 {
+            let base = self.factorial();
+            if self.eat("^") {
+                let exponent = self.sign();
+                self.checked([base, exponent], base.powf(exponent))
+            } else {
+                base
+            }
+        }
+
+        fn factorial(&mut self) -> f64 {
+            let value = self.primary();
+            if self.eat("!") {
+                (1..=value as u64).product::<u64>() as f64
+            } else {
+                value
+            }
+        }
+
+        fn primary(&mut self) -> f64 {
+            if self.eat("(") {
+                let value = self.sum();
+                self.expect(")");
+                return value;
+            }
+            if self.eat("abs(") {
+                let value = self.sum().abs();
+                self.expect(")");
+                return value;
+            }
+            for name in ["max(", "min("].iter() {
+                if self.eat(name) {
+                    let first = self.sum();
+                    self.expect(",");
+                    let second = self.sum();
+                    self.expect(")");
+                    return if *name == "max(" { first.max(second) } else { first.min(second) };
+                }
+            }
+
+            let length = self.input.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(self.input.len());
+            let value = self.input[..length].parse().unwrap();
+            self.input = &self.input[length..];
+            value
+        }
+    }
+
+    #[test]
+    fn generated_strings_match_a_reference() {
+        for seed in 0..5000 {
+            let mut generator = Generator { state: seed };
+            let input = generator.text(6);
+            let mut reference = Reference { input: &input, failed: false };
+            let expected = reference.sum();
+            assert_eq!(reference.input, "", "seed {}: {}", seed, input);
+
+            match evaluate_str(&input) {
+                Ok(value) if !reference.failed => assert!(
+                    same_value(value, expected) || (value - expected).abs() <= 1e-9 * expected.abs().max(1.0),
+                    "seed {}: {} is {} instead of {}", seed, input, value, expected,
+                ),
+                Err(_) if reference.failed => {},
+                result => panic!("seed {}: {} is {:?} instead of {} (failed: {})", seed, input, result, expected, reference.failed),
+            }
+        }
+    }
+
+    #[test]
+    fn percent_sign() {
+        assert_eq!(evaluate_str("50%").unwrap(), 0.5);
+        assert_eq!(evaluate_str("200 * 5%").unwrap(), 10.0);
+        assert_eq!(evaluate_str("10 % 3").unwrap(), 1.0);
+        assert_eq!(evaluate_str("(50%) - 3").unwrap(), -2.5);
+        assert_eq!(evaluate_str("|50%| + 1").unwrap(), 1.5);
+        assert_eq!(evaluate_str("|50%| - 1").unwrap(), -0.5);
+
+        // The spaces around a sign don't decide between the two.
+        for input in ["50%-3", "50% -3", "50% - 3", "50 % - 3"].iter() {
+            assert_eq!(evaluate_str(input).unwrap(), 2.0, "{}", input);
+        }
+        assert_eq!(evaluate_str("50% + 1").unwrap(), 0.0);
+        assert_eq!(evaluate_str("(50%) + 1").unwrap(), 1.5);
+        assert_eq!(to_infix(&parse_str("(50%) - 3").unwrap()), "(50%) - 3");
+        assert_eq!(to_infix(&parse_str("(2 * 50%) + 1").unwrap()), "(2 * 50%) + 1");
+    }
+
+    #[test]
+    fn remainder_of_a_signed_operand() {
+        assert_eq!(evaluate_str("7 % -3").unwrap(), 1.0);
+        assert_eq!(evaluate_str("7 % +3").unwrap(), 1.0);
+        assert_eq!(evaluate_str("7 % −3").unwrap(), 1.0);
+        assert_eq!(evaluate_str("7 % --3").unwrap(), 1.0);
+        assert_eq!(evaluate_str("10 % |0-3|").unwrap(), 1.0);
+        assert_eq!(to_infix(&parse_str("7 % -3").unwrap()), "7 % -3");
+    }
+
+    #[test]
+    fn deep_trees() {
+        let minuses = "-".repeat(200000) + "1";
+        assert!(matches!(parse_str(&minuses), Err(Error::Parse(ParseError::DepthLimitExceeded { limit: MAX_DEPTH, .. }))));
+        assert_eq!(evaluate_str(&minuses).unwrap(), 1.0);
+
+        let powers = vec!["1"; 200000].join("^");
+        assert!(matches!(parse_str(&powers), Err(Error::Parse(ParseError::DepthLimitExceeded { .. }))));
+        let sums = vec!["1"; 200000].join("+");
+        assert!(matches!(parse_str(&sums), Err(Error::Parse(ParseError::DepthLimitExceeded { .. }))));
+
+        let deep = "-".repeat(MAX_DEPTH - 1) + "1";
+        assert_eq!(eval(&parse_str(&deep).unwrap()), -1.0);
+        assert_eq!(to_infix(&parse_str(&deep).unwrap()), deep);
+        assert!(parse_str(&("-".repeat(MAX_DEPTH) + "1")).is_err());
+
+        // The tree of the sample expression is more than five hundred levels deep.
+        assert_eq!(eval(&parse_str(include_str!("expression.txt")).unwrap()), -56.0);
+    }
+
+    #[test]
+    fn deep_json() {
+        let json = "{\"op\":\"-\",\"operand\":".repeat(200000) + "{\"num\":1}" + &"}".repeat(200000);
+        assert!(matches!(from_json(&json), Err(JsonError::DepthLimitExceeded { limit: MAX_DEPTH, .. })));
+
+        let tree = parse_str(&("-".repeat(MAX_DEPTH - 1) + "1")).unwrap();
+        assert_eq!(from_json(&to_json(&tree)).unwrap(), tree);
+    }
+
+    #[test]
+    fn json() {
+        let tree = parse_str("1 + 2 * 3").unwrap();
+        assert_eq!(to_json(&tree), r#"{"op":"+","left":{"num":1},"right":{"op":"*","left":{"num":2},"right":{"num":3}}}"#);
+        let tree = parse_str("-sqrt(4)!").unwrap();
+        assert_eq!(to_json(&tree), r#"{"op":"-","operand":{"op":"!","operand":{"fn":"sqrt","args":[{"num":4}]}}}"#);
+        let tree = parse_str("x ? log(2, 8) : 1").unwrap();
+        assert_eq!(to_json(&tree), r#"{"if":{"var":"x"},"then":{"fn":"log","args":[{"num":2},{"num":8}]},"else":{"num":1}}"#);
+
+        for input in ["1 + 2 * 3", "-(2.5 - 1)^3!", "sqrt(2) <= 1.5", "+1 % 7", "max(1, 2, 3)"].iter() {
+            let tree = parse_str(input).unwrap();
+            assert_eq!(from_json(&to_json(&tree)).unwrap(), tree);
+        }
+
+        let json = r#" { "op" : "-", "right": {"num": 1}, "left": {"num": 3.5} } "#;
+        assert_eq!(from_json(json).unwrap(), parse_str("3.5-1").unwrap());
+        assert!(from_json(r#"{"op":"?","left":{"num":1},"right":{"num":1}}"#).is_err());
+        assert!(from_json("{").is_err());
+        assert!(from_json(r#"{"num":1}x"#).is_err());
+    }
+
+    #[test]
+    fn json_escapes() {
+        let tree = Expr::Variable(String::from("a \"b\" \\ c\n"));
+        assert_eq!(to_json(&tree), r#"{"var":"a \"b\" \\ c\u000a"}"#);
+        assert_eq!(from_json(&to_json(&tree)).unwrap(), tree);
+        assert_eq!(from_json(r#"{"var":"x\/\t"}"#).unwrap(), Expr::Variable(String::from("x/\t")));
+        assert!(matches!(from_json(r#"{"var":"\q"}"#), Err(JsonError::UnexpectedCharacter { ch: 'q', .. })));
+    }
+
+    #[test]
+    fn json_non_finite_numbers() {
+        assert_eq!(to_json(&Expr::Number(f64::INFINITY)), r#"{"num":null}"#);
+        assert_eq!(to_json(&Expr::Number(f64::NAN)), r#"{"num":null}"#);
+        assert!(matches!(from_json(r#"{"num":null}"#), Ok(Expr::Number(number)) if number.is_nan()));
+        assert!(!semantically_eq(&Expr::Number(f64::INFINITY), &Expr::Number(f64::NAN)));
+    }
+
+    #[test]
+    fn json_custom_operator() {
+        let tree = Expr::BinaryOp { op: TokenType::Custom('@'), left: Box::new(Expr::Number(1.0)), right: Box::new(Expr::Number(2.0)) };
+        assert_eq!(to_json(&tree), r#"{"op":"@","left":{"num":1},"right":{"num":2}}"#);
+        assert_eq!(to_infix(&tree), "1 @ 2");
+    }
+
+    #[test]
+    fn json_functions() {
+        assert_eq!(from_json(r#"{"fn":"foo","args":[]}"#), Err(JsonError::UnknownFunction { name: String::from("foo"), position: 0 }));
+        let error = from_json(r#"{"fn":"sqrt","args":[]}"#).unwrap_err();
+        assert_eq!(error, JsonError::WrongArgumentCount { name: String::from("sqrt"), expected: 1, found: 0, position: 0 });
+        assert_eq!(error.to_string(), "sqrt takes 1 argument(s) but 0 given at position 0.");
+        assert!(matches!(from_json(r#"{"fn":"max","args":[]}"#), Err(JsonError::WrongArgumentCount { expected: 1, found: 0, .. })));
+        assert!(from_json(r#"{"fn":"max","args":[{"num":1},{"num":2}]}"#).is_ok());
+        assert!(matches!(from_json(r#"{"fn":"max","args":[1]}"#), Err(JsonError::UnexpectedCharacter { ch: '1', .. })));
+
+        let json = "{\"fn\":\"abs\",\"args\":[".repeat(200000) + "{\"num\":1}" + &"]}".repeat(200000);
+        assert!(matches!(from_json(&json), Err(JsonError::DepthLimitExceeded { .. })));
+    }
+
+    #[test]
+    fn combined_settings() {
+        let settings = Settings { decimal_comma: true, exact: true, ..Settings::default() };
+        assert_eq!(evaluate_with_settings("1,5 + 2,5", &settings).unwrap(), 4.0);
+        assert!(matches!(evaluate_with_settings("0,1000000000000000055511151231257827", &settings), Err(Error::Scan(ScanError::PrecisionLoss { .. }))));
+        assert!(matches!(evaluate_with_settings("9007199254740993", &settings), Err(Error::Scan(ScanError::PrecisionLoss { .. }))));
+
+        let settings = Settings { decimal_comma: true, tight_minus: true, ..Settings::default() };
+        assert_eq!(evaluate_with_settings("-1,5^2", &settings).unwrap(), 2.25);
+        assert_eq!(evaluate_with_settings("max(1,5; 2)", &settings).unwrap(), 2.0);
+
+        let settings = Settings { decimal_comma: true, max_input_length: Some(3), ..Settings::default() };
+        assert!(matches!(evaluate_with_settings("1,5 + 1", &settings), Err(Error::Scan(ScanError::InputTooLong { .. }))));
+        assert_eq!(evaluate_with_decimal_comma("1,5").unwrap(), 1.5);
+        assert!(evaluate_exact("9007199254740993").is_err());
+    }
+
+    #[test]
+    fn factorials() {
+        assert_eq!(evaluate_int("5!").unwrap(), 120);
+        assert_eq!(evaluate_int("20!").unwrap(), 2432902008176640000);
+        assert_eq!(evaluate_int("0!").unwrap(), 1);
+        let error = evaluate_int("21!").unwrap_err();
+        assert!(matches!(error, Error::Parse(ParseError::FactorialOverflow { operand: 21, .. })));
+        assert_eq!(error.to_string(), "21! does not fit in an integer at line 1, column 3.");
+        assert!(matches!(evaluate_int("(3 + 22)!"), Err(Error::Parse(ParseError::FactorialOverflow { operand: 25, .. }))));
+
+        assert_eq!(evaluate_str("5!").unwrap(), 120.0);
+        assert_eq!(evaluate_str("20!").unwrap(), 2432902008176640000.0);
+        assert_eq!(evaluate_str("171!").unwrap(), f64::INFINITY);
+    }
+
+    #[test]
+    fn factorial_warnings() {
+        let settings = Settings::default();
+        assert_eq!(evaluate_with_warnings("22!", &settings).unwrap().1, vec![]);
+
+        let (result, warnings) = evaluate_with_warnings("1 + 25!", &settings).unwrap();
+        assert!((result / 1.5511210043330986e25 - 1.0).abs() < 1e-12);
+        assert_eq!(warnings, vec![Warning::InexactFactorial { operand: 25.0, position: Position { line: 1, column: 7 }, span: 6..7 }]);
+        assert_eq!(warnings[0].to_string(), "25! is only approximate at line 1, column 7.");
+
+        // Infinity is plainly not the factorial, so there is nothing to warn about.
+        assert_eq!(evaluate_with_warnings("1000!", &settings).unwrap(), (f64::INFINITY, vec![]));
+    }
+
+    #[test]
+    fn integer_mode() {
+        assert_eq!(evaluate_int("7 / 2").unwrap(), 3);
+        assert_eq!(evaluate_int("-7 / 2").unwrap(), -3);
+        assert!(matches!(evaluate_int("9999999999 * 9999999999"), Err(Error::Parse(ParseError::Overflow { .. }))));
+        assert!(matches!(evaluate_int("2^62 + 2^62"), Err(Error::Parse(ParseError::Overflow { .. }))));
+        assert!(matches!(evaluate_int("1 / 0"), Err(Error::Parse(ParseError::DivisionByZero { .. }))));
+        assert!(matches!(evaluate_int("sqrt(4)"), Err(Error::Parse(ParseError::NotInIntegerMode { .. }))));
+        assert!(matches!(evaluate_int("x + 1"), Err(Error::Parse(ParseError::UnknownIdentifier { .. }))));
+    }
+
+    #[test]
+    fn integer_literals() {
+        assert_eq!(evaluate_int("9223372036854775807").unwrap(), i64::MAX);
+        assert_eq!(evaluate_int("9007199254740993").unwrap(), 9007199254740993);
+        assert_eq!(evaluate_int("0x7FFFFFFFFFFFFFFF").unwrap(), i64::MAX);
+        assert_eq!(evaluate_int("1.5e3").unwrap(), 1500);
+        assert_eq!(evaluate_int("100e-2").unwrap(), 1);
+        assert_eq!(evaluate_int("1_000e-3").unwrap(), 1);
+        assert_eq!(evaluate_int("0.000e5").unwrap(), 0);
+
+        let error = evaluate_int("9223372036854775808").unwrap_err();
+        assert!(matches!(error, Error::Scan(ScanError::IntegerOutOfRange { .. })));
+        assert_eq!(error.to_string(), "9223372036854775808 does not fit in a 64-bit integer at line 1, column 1.");
+        assert!(matches!(evaluate_int("1e19"), Err(Error::Scan(ScanError::IntegerOutOfRange { .. }))));
+        assert!(matches!(evaluate_int("1e99999999999999999999"), Err(Error::Scan(ScanError::IntegerOutOfRange { .. }))));
+        assert!(matches!(evaluate_int("0x8000000000000000"), Err(Error::Scan(ScanError::IntegerOutOfRange { .. }))));
+
+        // The minimum has no positive counterpart, so the minus is read with it.
+        assert_eq!(evaluate_int("-9223372036854775808").unwrap(), i64::MIN);
+        assert_eq!(evaluate_int("-9_223_372_036_854_775_808 + 1").unwrap(), i64::MIN + 1);
+        assert_eq!(evaluate_int("3 + -9223372036854775808 / 2").unwrap(), 3 + i64::MIN / 2);
+        let mut scanner = Scanner::new("-9223372036854775808");
+        scanner.integer = true;
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!((tokens.len(), tokens[0].lexeme, tokens[0].integer), (1, "-9223372036854775808", Some(i64::MIN)));
+        assert!(matches!(evaluate_int("--9223372036854775808"), Err(Error::Parse(ParseError::Overflow { .. }))));
+        assert!(matches!(evaluate_int("1 -9223372036854775808"), Err(Error::Scan(ScanError::IntegerOutOfRange { .. }))));
+        assert!(matches!(evaluate_int("-9223372036854775809"), Err(Error::Scan(ScanError::IntegerOutOfRange { .. }))));
+        assert_eq!(evaluate_str("-9223372036854775808").unwrap(), -9223372036854775808.0);
+
+        assert!(matches!(evaluate_int("1.5"), Err(Error::Scan(ScanError::NotAnInteger { .. }))));
+        assert!(matches!(evaluate_int("1e-3"), Err(Error::Scan(ScanError::NotAnInteger { .. }))));
+        assert!(matches!(evaluate_int("2pi"), Err(Error::Scan(ScanError::NotAnInteger { .. }))));
+    }
+
+    #[test]
+    fn trailing_operators() {
+        match validate("3 +") {
+            Err(Error::Parse(ParseError::MissingOperand { lexeme, span, .. })) => {
+                assert_eq!(lexeme, "+");
+                assert_eq!(span, 2..3);
+            },
+            result => panic!("{:?}", result),
+        }
+        assert!(matches!(validate("3 * /"), Err(Error::Parse(ParseError::UnexpectedOperator { .. }))));
+        assert!(matches!(validate("3 * -"), Err(Error::Parse(ParseError::MissingOperand { .. }))));
+        assert!(matches!(validate("1 ? 2 :"), Err(Error::Parse(ParseError::MissingOperand { .. }))));
+        assert!(matches!(evaluate_str("3 +"), Err(Error::Parse(ParseError::MissingOperand { .. }))));
+
+        assert!(validate("-(3)! + |1| ? max(1, 2, 3) : 0").is_ok());
+        assert_eq!(evaluate_str("3 + 4 * 2").unwrap(), 11.0);
+
+        // The parser, not the output, finds the operator without its operands.
+        let table = PrecedenceTable::new();
+        let tokens = tokenize("1 + 2").unwrap();
+        let mut parser = Parser::new(&tokens, Validation, &table);
+        parser.operators.push(&tokens[1]);
+        assert!(matches!(parser.apply_top(), Err(ParseError::MissingOperand { .. })));
+    }
+
+    #[test]
+    fn unparsable_number() {
+        // The scanner never makes such a lexeme itself, so it is set up by hand.
+        let mut scanner = Scanner::new("1 + 1.2.3");
+        scanner.start = 4;
+        scanner.current = 9;
+        scanner.start_position = Position { line: 1, column: 5 };
+        let error = scanner.get_float_number().unwrap_err();
+        assert!(matches!(error, ScanError::InvalidNumber { ref lexeme, span: Range { start: 4, end: 9 }, .. } if lexeme == "1.2.3"));
+        assert_eq!(error.to_string(), "Invalid number: 1.2.3 at line 1, column 5.");
+    }
+
+    #[test]
+    fn exponentiation() {
+        assert_eq!(evaluate_str("2^3^2").unwrap(), 512.0);
+        assert_eq!(evaluate_str("(2^3)^2").unwrap(), 64.0);
+        assert_eq!(evaluate_str("2*3^2").unwrap(), 18.0);
+        assert_eq!(evaluate_str("2^3*2").unwrap(), 16.0);
+        assert_eq!(evaluate_str("-3^2").unwrap(), -9.0);
+        assert_eq!(evaluate_str("2^-1").unwrap(), 0.5);
+    }
+
+    #[test]
+    fn remainder() {
+        assert_eq!(evaluate_str("10 % 3").unwrap(), 1.0);
+        assert_eq!(evaluate_str("10.5 % 2").unwrap(), 0.5);
+        assert_eq!(evaluate_str("(10 % 4) * 2").unwrap(), 4.0);
+        assert_eq!(evaluate_str("-7 % 3").unwrap(), -1.0);
+        assert_eq!(evaluate_str("7 % (-3)").unwrap(), 1.0);
+    }
+
+    #[test]
+    fn scientific_notation() {
+        assert_eq!(evaluate_str("1e3").unwrap(), 1000.0);
+        assert_eq!(evaluate_str("1.5E+2").unwrap(), 150.0);
+        assert_eq!(evaluate_str("6.022e23").unwrap(), 6.022e23);
+        assert_eq!(evaluate_str("2.5e-3").unwrap(), 0.0025);
+        assert_eq!(tokenize("1.5E+2").unwrap().len(), 1);
+
+        // Without digits after it, "e" is not a part of the number: 1e is 1 times e.
+        assert_eq!(evaluate_str("e").unwrap(), consts::E);
+        assert_eq!(evaluate_str("1e").unwrap(), consts::E);
+        assert!(matches!(evaluate_str("1e+"), Err(Error::Parse(ParseError::MissingOperand { .. }))));
+    }
+
+    #[test]
+    fn digit_separators() {
+        assert_eq!(evaluate_str("1_000_000 + 500").unwrap(), 1000500.0);
+        assert_eq!(evaluate_str("1_000 * 2").unwrap(), 2000.0);
+        assert_eq!(evaluate_str("1_0.2_5e1_0").unwrap(), 10.25e10);
+
+        assert!(matches!(evaluate_str("5_"), Err(Error::Scan(ScanError::MisplacedUnderscore { position: Position { line: 1, column: 2 }, .. }))));
+        assert!(matches!(evaluate_str("5__5"), Err(Error::Scan(ScanError::MisplacedUnderscore { position: Position { line: 1, column: 2 }, .. }))));
+        assert!(matches!(evaluate_str("_5"), Err(Error::Scan(ScanError::UnexpectedCharacter { ch: '_', position: Position { line: 1, column: 1 }, .. }))));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert!(matches!(evaluate_str("1 / 0"), Err(Error::Parse(ParseError::DivisionByZero { position: Position { line: 1, column: 3 }, .. }))));
+        assert!(matches!(evaluate_str("1 / (2 - 2)"), Err(Error::Parse(ParseError::DivisionByZero { position: Position { line: 1, column: 3 }, .. }))));
+        assert_eq!(evaluate_str("0 / 1").unwrap(), 0.0);
+
+        // Every operator that divides reports the same error, whichever way the expression is evaluated.
+        for input in ["1 / 0", "1 // 0", "5 % 0"].iter() {
+            assert!(matches!(evaluate_str(input), Err(Error::Parse(ParseError::DivisionByZero { .. }))), "{}", input);
+            assert!(matches!(evaluate_int(input), Err(Error::Parse(ParseError::DivisionByZero { .. }))), "{}", input);
+            assert!(compile(input).unwrap().run(&HashMap::new()).unwrap_err().to_string().starts_with("Division by zero"), "{}", input);
+            assert_eq!(eval_checked(&parse_str(input).unwrap()), Err(EvalError::DivisionByZero), "{}", input);
+        }
+    }
+
+    #[test]
+    fn factorial() {
+        assert_eq!(evaluate_str("5!").unwrap(), 120.0);
+        assert_eq!(evaluate_str("3! + 2").unwrap(), 8.0);
+        assert_eq!(evaluate_str("(2+1)!").unwrap(), 6.0);
+        assert_eq!(evaluate_str("0!").unwrap(), 1.0);
+        assert_eq!(evaluate_str("-3!").unwrap(), -6.0);
+        assert_eq!(evaluate_str("2^3!").unwrap(), 64.0);
+        assert_eq!(evaluate_str("3!!").unwrap(), 720.0);
+        assert_eq!(evaluate_str("sqrt(4)!").unwrap(), 2.0);
+        assert_eq!(eval(&parse_str("4!").unwrap()), 24.0);
+
+        assert!(matches!(evaluate_str("(-3)!"), Err(Error::Parse(ParseError::InvalidFactorial { .. }))));
+        assert!(matches!(evaluate_str("2.5!"), Err(Error::Parse(ParseError::InvalidFactorial { .. }))));
+        assert!(matches!(evaluate_str("!3"), Err(Error::Scan(_))));
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(evaluate_str("3 > 2").unwrap(), 1.0);
+        assert_eq!(evaluate_str("3 < 2").unwrap(), 0.0);
+        assert_eq!(evaluate_str("2 <= 2").unwrap(), 1.0);
+        assert_eq!(evaluate_str("2 >= 3").unwrap(), 0.0);
+        assert_eq!(evaluate_str("2 + 2 == 4").unwrap(), 1.0);
+        assert_eq!(evaluate_str("2 != 3").unwrap(), 1.0);
+
+        // Left to right: (3 > 2) > 1 is 1 > 1.
+        assert_eq!(evaluate_str("3 > 2 > 1").unwrap(), 0.0);
+        assert_eq!(evaluate_str("1 < 2 < 3").unwrap(), 1.0);
+
+        assert_eq!(evaluate_str("3!=6").unwrap(), 1.0);
+        assert_eq!(evaluate_str("3! == 6").unwrap(), 1.0);
+        assert!(evaluate_str("1 = 1").is_err());
+    }
+
+    #[test]
+    fn variables() {
+        let mut bindings = HashMap::new();
+        bindings.insert(String::from("x"), 3.0);
+        bindings.insert(String::from("y"), 1.0);
+        bindings.insert(String::from("pi"), 3.0);
+
+        assert_eq!(evaluate_with("x * 2 + y", &bindings).unwrap(), 7.0);
+        assert_eq!(evaluate_with("2x", &bindings).unwrap(), 6.0);
+        assert_eq!(evaluate_with("-x", &bindings).unwrap(), -3.0);
+        assert_eq!(evaluate_with("x - 1", &bindings).unwrap(), 2.0);
+        assert!(matches!(evaluate_with("z + 1", &bindings), Err(Error::Parse(ParseError::UnknownIdentifier { ref name, .. })) if name == "z"));
+
+        // A constant cannot be shadowed.
+        assert_eq!(evaluate_with("pi", &bindings).unwrap(), consts::PI);
+    }
+
+    #[test]
+    fn reverse_polish_notation() {
+        assert_eq!(to_rpn("3 + 4 * 2").unwrap(), ["3", "4", "2", "*", "+"]);
+        assert_eq!(to_rpn("(3 + 4) * 2").unwrap(), ["3", "4", "+", "2", "*"]);
+        assert_eq!(to_rpn("-3 - 4").unwrap(), ["3", "neg", "4", "-"]);
+        assert_eq!(to_rpn("2^3^2").unwrap(), ["2", "3", "2", "^", "^"]);
+        assert_eq!(to_rpn("sqrt(x)+1").unwrap(), ["x", "sqrt", "1", "+"]);
+        assert!(to_rpn("(1").is_err());
+    }
+
+    #[test]
+    fn leading_decimal_point() {
+        assert_eq!(evaluate_str(".5 + .5").unwrap(), 1.0);
+        assert_eq!(evaluate_str(".25 * 4").unwrap(), 1.0);
+        assert_eq!(evaluate_str(".5e1").unwrap(), 5.0);
+        assert_eq!(evaluate_str("-.5").unwrap(), -0.5);
+        assert_eq!(tokenize(".5.5").unwrap().len(), 2);
+
+        assert!(matches!(evaluate_str("1 + ."), Err(Error::Scan(ScanError::UnexpectedCharacter { ch: '.', .. }))));
+        assert!(matches!(evaluate_str(". 5"), Err(Error::Scan(ScanError::UnexpectedCharacter { ch: '.', .. }))));
     }
-}
 
-const UMINUS: TokenType = TokenType { uid: 1, precedence: 4 };
-const STAR: TokenType = TokenType { uid: 2, precedence: 3 };
-const SLASH: TokenType = TokenType { uid: 3, precedence: 3 };
-const PLUS: TokenType = TokenType { uid: 4, precedence: 2 };
-const MINUS: TokenType = TokenType { uid: 5, precedence: 2 };
+    #[test]
+    fn unicode_signs() {
+        assert_eq!(evaluate_str("−3 × 4 ÷ 2 − 1").unwrap(), evaluate_str("-3 * 4 / 2 - 1").unwrap());
+        assert_eq!(evaluate_str("2 − −1").unwrap(), 3.0);
+        assert_eq!(tokenize("−1").unwrap()[0].ttype, TokenType::UMinus);
+    }
 
-const NUMBER: TokenType = TokenType { uid: 6, precedence: 0 };
-const LEFT_PAREN: TokenType = TokenType { uid: 7, precedence: 0 };
-const RIGHT_PAREN: TokenType = TokenType { uid: 8, precedence: 0 };
+    #[test]
+    fn brackets_and_braces() {
+        assert_eq!(evaluate_str("[1 + 2] * {3}").unwrap(), 9.0);
+        assert_eq!(evaluate_str("{[(1+1)*2]-1}").unwrap(), 3.0);
+        assert_eq!(evaluate_str("2[3]").unwrap(), 6.0);
 
-#[derive(Debug)]
-struct Token {
-    ttype: TokenType,
-    lexeme: String,
-    literal: Option<f64>,
-    start_at: usize,
-    end_at: usize,
-}
+        assert!(matches!(evaluate_str("[1 + 2)"), Err(Error::Parse(ParseError::MismatchedGrouping { .. }))));
+        assert!(matches!(evaluate_str("{1 + 2]"), Err(Error::Parse(ParseError::MismatchedGrouping { .. }))));
+        assert!(matches!(evaluate_str("[1"), Err(Error::Parse(ParseError::MismatchedLeftParen { .. }))));
+        assert!(matches!(evaluate_str("1]"), Err(Error::Parse(ParseError::MismatchedRightParen { .. }))));
+    }
 
-struct Scanner {
-    chars: Vec<char>,
-    tokens: Vec<Token>,
-    start: usize,
-    current: usize,
-}
+    #[test]
+    fn all_errors_at_once() {
+        let errors = evaluate_collecting_errors("1 $ 2 ~ (3 + 5__5").unwrap_err();
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+        let errors = evaluate_collecting_errors("(1]) + [2").unwrap_err();
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert_eq!(evaluate_collecting_errors("1/0").unwrap_err().len(), 1);
+        assert_eq!(evaluate_collecting_errors("1+2").unwrap(), 3.0);
+    }
 
-impl Scanner {
+    #[test]
+    fn abs_and_unary_plus() {
+        assert_eq!(evaluate_str("+5").unwrap(), 5.0);
+        assert_eq!(evaluate_str("abs(-7)+1").unwrap(), 8.0);
+        assert_eq!(evaluate_str("-(+3)").unwrap(), -3.0);
+        assert_eq!(evaluate_str("2 + +3").unwrap(), 5.0);
+        assert_eq!(evaluate_int("+5 - -2").unwrap(), 7);
+        assert_eq!(to_rpn("+3 - 1").unwrap(), ["3", "1", "-"]);
+    }
 
-    fn new(chars: Vec<char>) -> Self {
-        Self {
-            chars: chars,
-            tokens: vec![],
-            start: 0,
-            current: 0,
+    #[test]
+    fn simplification() {
+        let simplified = |input: &str| simplify(parse_str(input).unwrap());
+        let x = || Expr::Variable(String::from("x"));
+
+        assert_eq!(simplified("2 + 3 * 4"), Expr::Number(14.0));
+        assert_eq!(simplified("sqrt(16) - -1"), Expr::Number(5.0));
+        assert_eq!(simplified("x * 1"), x());
+        assert_eq!(simplified("0 + x * (3 - 2)"), x());
+        assert_eq!(simplified("(y + 2) * 0"), Expr::Number(0.0));
+        assert_eq!(simplified("x * (2 + 3)"), Expr::BinaryOp { op: TokenType::Star, left: Box::new(x()), right: Box::new(Expr::Number(5.0)) });
+
+        let once = simplified("x ^ (1 + 1) + 0 * y - sin(x)");
+        assert_eq!(simplify(from_json(&to_json(&once)).unwrap()), once);
+    }
+
+    #[test]
+    fn minimal_parentheses() {
+        let printed = |input: &str| to_infix(&parse_str(input).unwrap());
+
+        assert_eq!(printed("(1 + 2) * 3"), "(1 + 2) * 3");
+        assert_eq!(printed("1 + (2 * 3)"), "1 + 2 * 3");
+        assert_eq!(printed("(2^3)^2"), "(2 ^ 3) ^ 2");
+        assert_eq!(printed("2^(3^2)"), "2 ^ 3 ^ 2");
+        assert_eq!(printed("1 - (2 - 3)"), "1 - (2 - 3)");
+        assert_eq!(printed("(1 - 2) - 3"), "1 - 2 - 3");
+        assert_eq!(printed("(-3)^2"), "(-3) ^ 2");
+        assert_eq!(printed("-(3^2)"), "-3 ^ 2");
+        assert_eq!(printed("2^-1"), "2 ^ -1");
+        assert_eq!(printed("(2+1)!"), "(2 + 1)!");
+        assert_eq!(printed("-(1+2)"), "-(1 + 2)");
+        assert_eq!(printed("sqrt((4))"), "sqrt(4)");
+
+        // A negative number in the tree is printed like a negated one.
+        let tree = Expr::BinaryOp { op: TokenType::Caret, left: Box::new(Expr::Number(-3.0)), right: Box::new(Expr::Number(2.0)) };
+        assert_eq!(to_infix(&tree), "(-3) ^ 2");
+
+        for input in ["(1 + 2) * 3", "1 - (2 - 3) / 4", "-(2 ^ 3)!", "2 ^ (1 < 2) % 3"].iter() {
+            let tree = parse_str(input).unwrap();
+            assert_eq!(eval(&parse_str(&to_infix(&tree)).unwrap()), eval(&tree), "{}", input);
         }
     }
 
-    fn scan_tokens(&mut self) -> &Vec<Token> {
-        while !self.is_at_end() && self.peek() != '\n' {
-            self.start = self.current;
-            self.scan_token();
+    #[test]
+    fn whitespace() {
+        assert_eq!(evaluate_str("1\t+\t2").unwrap(), 3.0);
+        assert_eq!(evaluate_str("\u{a0}2 *\u{2003}3 \r").unwrap(), 6.0);
+
+        // A line break still ends the line, with a carriage return before it or not.
+        assert_eq!(tokenize("1 +\r\n2").unwrap().len(), 2);
+        let mut scanner = Scanner::new("1 + 2\r\n3 * 4\r\n");
+        assert_eq!(scanner.scan_tokens().unwrap().len(), 3);
+        scanner.next_line();
+        assert_eq!(scanner.scan_tokens().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn spans() {
+        let span = |input: &str| evaluate_str(input).unwrap_err().span();
+
+        assert_eq!(span("1 + $"), Some(4..5));
+        assert_eq!(span("2 * foo(3)"), Some(4..7));
+        assert_eq!(span("10 / (5 - 5)"), Some(3..4));
+        assert_eq!(span("1 + (2"), Some(4..5));
+        assert_eq!(span("(1 + 2]"), Some(6..7));
+        assert_eq!(span("1 + 2)"), Some(5..6));
+        assert_eq!(span("3.5!"), Some(3..4));
+        assert_eq!(span("1 + longname"), Some(4..12));
+        assert_eq!(span("5__5"), Some(1..2));
+        assert_eq!(span("1 + 0x"), Some(4..6));
+        assert_eq!(span("log(1)"), Some(0..3));
+        assert_eq!(span("1, 2"), Some(1..2));
+
+        // The offsets count characters, not bytes.
+        assert_eq!(span("−1 × $"), Some(5..6));
+        assert_eq!(span("1 + 2 +"), Some(6..7));
+    }
+
+    #[test]
+    fn tokens_one_by_one() {
+        let mut scanner = Scanner::new("2(3 + x) - $ 4\n5");
+        let types: Vec<TokenType> = scanner.by_ref().take(6).map(|token| token.unwrap().ttype).collect();
+        assert_eq!(types, vec![TokenType::Number, TokenType::Star, TokenType::LeftParen, TokenType::Number, TokenType::Plus, TokenType::Identifier]);
+        assert_eq!(scanner.next().unwrap().unwrap().ttype, TokenType::RightParen);
+        assert_eq!(scanner.next().unwrap().unwrap().ttype, TokenType::Minus);
+        assert!(scanner.next().unwrap().is_err());
+        assert_eq!(scanner.next().unwrap().unwrap().lexeme, "4");
+        assert!(scanner.next().is_none());
+        scanner.next_line();
+        assert_eq!(scanner.next().unwrap().unwrap().lexeme, "5");
+        assert!(scanner.next().is_none());
+
+        for input in ["1 + 2 * -3", "2pi(1)[2]", "sqrt(4) ^ 2!", "  ", "1_000.5e-3 >= 0x1F"].iter() {
+            let iterated: Vec<&str> = Scanner::new(input).map(|token| token.unwrap().lexeme).collect();
+            let scanned: Vec<&str> = tokenize(input).unwrap().iter().map(|token| token.lexeme).collect();
+            assert_eq!(iterated, scanned);
         }
+    }
 
-        return &self.tokens;
+    #[test]
+    fn token_display() {
+        let tokens: Vec<String> = tokenize("-2 - (x)").unwrap().iter().map(|token| token.to_string()).collect();
+        assert_eq!(tokens, ["UMinus - at 0..1", "Number 2 at 1..2", "Minus - at 3..4", "LeftParen ( at 5..6", "Identifier x at 6..7", "RightParen ) at 7..8"]);
     }
 
-    fn scan_token(&mut self) {
-        let c = self.advance();
-        match c {
-            '+' => self.add_token(PLUS, None),
-            '-' => {
-                let tokens_count = self.tokens.len();
-                if tokens_count < 1 || (
-                    self.tokens[tokens_count-1].ttype != NUMBER &&
-                    self.tokens[tokens_count-1].ttype != RIGHT_PAREN
-                ) {
-                    self.add_token(UMINUS, None);
-                } else {
-                    self.add_token(MINUS, None);
-                }
-            },
-            '*' => self.add_token(STAR, None),
-            '/' => self.add_token(SLASH, None),
-            '(' => self.add_token(LEFT_PAREN, None),
-            ')' => self.add_token(RIGHT_PAREN, None),
-            _ => {
-                if c == ' ' {
-                    return;
-                } if c.is_digit(10) {
-                    self.number();
-                } else {
-                    panic!("Unexpected character: {} at position {}.", c, self.current-1);
-                }
-            },
+    #[test]
+    fn empty_expressions() {
+        for input in ["", "   ", "\n", " \t\r\n "].iter() {
+            assert!(matches!(evaluate_str(input), Err(Error::Parse(ParseError::EmptyExpression))), "{:?}", input);
         }
+        assert!(matches!(evaluate_int(""), Err(Error::Parse(ParseError::EmptyExpression))));
+        assert!(matches!(parse_str(""), Err(Error::Parse(ParseError::EmptyExpression))));
+        assert!(matches!(evaluate_collecting_errors("  ").unwrap_err()[0], Error::Parse(ParseError::EmptyExpression)));
+        assert!(evaluate_str("").unwrap_err().span().is_none());
+        assert_eq!(evaluate_str("").unwrap_err().to_string(), "The expression is empty.");
     }
 
-    fn is_at_end(&self) -> bool {
-        self.current >= self.chars.len()
+    #[test]
+    fn borrowed_lexemes() {
+        let source = String::from("12.5e1 × −x_1 >= 0x1F");
+        let tokens = tokenize(&source).unwrap();
+        let lexemes: Vec<&str> = tokens.iter().map(|token| token.lexeme).collect();
+        assert_eq!(lexemes, ["12.5e1", "×", "−", "x_1", ">=", "0x1F"]);
+
+        // Every lexeme is a slice of the source itself, not a copy.
+        for token in &tokens {
+            let offset = token.lexeme.as_ptr() as usize - source.as_ptr() as usize;
+            assert_eq!(&source[offset..offset + token.lexeme.len()], token.lexeme);
+        }
+        assert_eq!(tokens[0].lexeme.as_ptr(), source.as_ptr());
+        assert_eq!(tokenize("2(3)").unwrap()[1].lexeme, "*");
     }
 
-    fn advance(&mut self) -> char {
-        let c = self.chars[self.current];
-        self.current += 1;
-        return c;
+    #[test]
+    fn associativity() {
+        assert_eq!(evaluate_str("2 ^ 3 ^ 2").unwrap(), 512.0);
+        assert_eq!(evaluate_str("2 ^ -1 ^ 2").unwrap(), 0.5);
+        assert_eq!(evaluate_str("8 - 3 - 2").unwrap(), 3.0);
+        assert_eq!(evaluate_str("64 / 4 / 2").unwrap(), 8.0);
+        assert_eq!(TokenType::Caret.associativity(), Associativity::Right);
+        assert_eq!(TokenType::Minus.associativity(), Associativity::Left);
+
+        let mut registry = OperatorRegistry::new();
+        registry.register('@', 3, Associativity::Right, |a, b| a - b);
+        registry.register('~', 3, Associativity::Left, |a, b| a - b);
+        assert_eq!(registry.evaluate("8 @ 3 @ 2").unwrap(), 7.0);
+        assert_eq!(registry.evaluate("8 ~ 3 ~ 2").unwrap(), 3.0);
+
+        let mut table = PrecedenceTable::new();
+        table.set(TokenType::Minus, 2, Associativity::Right);
+        assert_eq!(evaluate_with_table("8 - 3 - 2", &table).unwrap(), 7.0);
     }
 
-    fn add_token(&mut self, ttype: TokenType, literal: Option<f64>) {
-        self.tokens.push(Token {
-            ttype: ttype,
-            literal: literal,
-            lexeme: self.make_lexeme(),
-            start_at: self.start,
-            end_at: self.current - 1,
-        });
+    #[test]
+    fn rounding() {
+        let value = |input: &str| evaluate_str(input).unwrap();
+
+        assert_eq!(value("ceil(2.1)"), 3.0);
+        assert_eq!(value("ceil(-2.1)"), -2.0);
+        assert_eq!(value("ceil(4)"), 4.0);
+        assert_eq!(value("floor(2.9)"), 2.0);
+        assert_eq!(value("floor(-2.1)"), -3.0);
+        assert_eq!(value("floor(-4)"), -4.0);
+        assert_eq!(value("trunc(2.9)"), 2.0);
+        assert_eq!(value("trunc(-2.9)"), -2.0);
+        assert_eq!(value("trunc(7)"), 7.0);
+        assert_eq!(value("round(2.4)"), 2.0);
+        assert_eq!(value("round(-2.6)"), -3.0);
+        assert_eq!(value("round(3)"), 3.0);
+
+        // Half away from zero, like f64::round.
+        assert_eq!(value("round(2.5)"), 3.0);
+        assert_eq!(value("round(-2.5)"), -3.0);
+
+        assert_eq!(value("2 * floor(7 / 2) + 1"), 7.0);
+        assert!(matches!(evaluate_str("round(1, 2)"), Err(Error::Parse(ParseError::WrongArgumentCount { .. }))));
     }
 
-    fn number(&mut self) {
-        while self.peek().is_digit(10) {
-            self.advance();
+    #[test]
+    fn degrees() {
+        let settings = Settings { degrees: true, ..Settings::default() };
+        let in_degrees = |input: &str| evaluate_with_settings(input, &settings).unwrap();
+
+        assert!((in_degrees("sin(90)") - 1.0).abs() < 1e-12);
+        assert!((in_degrees("cos(180)") + 1.0).abs() < 1e-12);
+        assert!((in_degrees("tan(45)") - 1.0).abs() < 1e-12);
+        assert!((in_degrees("2 * sin(30)") - 1.0).abs() < 1e-12);
+        assert_eq!(in_degrees("sqrt(16)"), 4.0);
+
+        // The same expressions in radians.
+        assert_eq!(evaluate_str("sin(90)").unwrap(), 90f64.sin());
+        assert_eq!(evaluate_str("cos(180)").unwrap(), 180f64.cos());
+        assert!((evaluate_str("cos(pi)").unwrap() + 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn tree_equality() {
+        let tree = |input: &str| parse_str(input).unwrap();
+
+        assert!(structurally_eq(&tree("1+2"), &tree("1 + 2")));
+        assert!(!structurally_eq(&tree("1+2"), &tree("2+1")));
+        assert!(!structurally_eq(&tree("(1+2)+3"), &tree("1+(2+3)")));
+        assert!(!structurally_eq(&tree("2*3"), &tree("6")));
+        assert!(!structurally_eq(&tree("max(1, x)"), &tree("max(x, 1)")));
+        assert!(structurally_eq(&tree("max(1, x) > 0 ? -y : z!"), &tree("max(1,x)>0?-y:z!")));
+        assert!(structurally_eq(&Expr::Number(f64::NAN), &Expr::Number(f64::NAN)));
+
+        // Only the operands of a commutative operator may change places.
+        assert!(semantically_eq(&tree("1+2"), &tree("2+1")));
+        assert!(semantically_eq(&tree("x*(y+1)"), &tree("(1+y)*x")));
+        assert!(!semantically_eq(&tree("1-2"), &tree("2-1")));
+        assert!(!semantically_eq(&tree("4/2"), &tree("2/4")));
+        assert!(!semantically_eq(&tree("(1+2)+3"), &tree("1+(2+3)")));
+    }
+
+    #[test]
+    fn latex() {
+        let latex = |input: &str| to_latex(&parse_str(input).unwrap());
+
+        assert_eq!(latex("(1+2)/3"), r"\frac{1 + 2}{3}");
+        assert_eq!(latex("1/(2/x)"), r"\frac{1}{\frac{2}{x}}");
+        assert_eq!(latex("(1/2)/(3/4)"), r"\frac{\frac{1}{2}}{\frac{3}{4}}");
+        assert_eq!(latex("2^3^2"), "2^{3^{2}}");
+        assert_eq!(latex("(2^3)^2"), r"\left(2^{3}\right)^{2}");
+        assert_eq!(latex("(1/2)^2"), r"\left(\frac{1}{2}\right)^{2}");
+        assert_eq!(latex("2^(1+x)"), "2^{1 + x}");
+        assert_eq!(latex("2*x"), r"2 \cdot x");
+        assert_eq!(latex("(1+2)*3"), r"\left(1 + 2\right) \cdot 3");
+        assert_eq!(latex("1/2*3"), r"\frac{1}{2} \cdot 3");
+        assert_eq!(latex("1-(2-3)"), r"1 - \left(2 - 3\right)");
+        assert_eq!(latex("-x^2"), "-x^{2}");
+        assert_eq!(latex("(-x)^2"), r"\left(-x\right)^{2}");
+        assert_eq!(latex("sqrt(x)+abs(y)"), r"\sqrt{x} + \left|y\right|");
+        assert_eq!(latex("log(2, 8)"), r"\log_{2}\left(8\right)");
+        assert_eq!(latex("x <= 3"), r"x \le 3");
+        assert_eq!(latex("7 // 2"), r"\left\lfloor \frac{7}{2} \right\rfloor");
+        assert_eq!(latex("x ? 1 : 2"), r"\begin{cases} 1 & \text{if } x \\ 2 & \text{otherwise} \end{cases}");
+    }
+
+    #[test]
+    fn grouped_thousands() {
+        assert_eq!(group_thousands("1234567.89"), "1,234,567.89");
+        assert_eq!(group_thousands("123456"), "123,456");
+        assert_eq!(group_thousands("1000"), "1,000");
+        assert_eq!(group_thousands("999"), "999");
+        assert_eq!(group_thousands("0.5"), "0.5");
+        assert_eq!(group_thousands("-12"), "-12");
+        assert_eq!(group_thousands("-1234.5"), "-1,234.5");
+        assert_eq!(group_thousands("-100000.123456"), "-100,000.123456");
+        assert_eq!(group_thousands("inf"), "inf");
+        assert_eq!(group_thousands("NaN"), "NaN");
+
+        let options = Options { grouped: true, precision: Some(2), ..options() };
+        assert_eq!(format_result(1234567.891, &options), "1,234,567.89");
+        assert_eq!(format_result(-999.999, &options), "-1,000.00");
+    }
+
+    #[test]
+    fn scanner_never_panics() {
+        let pool: Vec<char> = "0123456789._eExX+-−*×/÷%^!=<>()[]{},;?:| \t\r\nabcfpisqrtlogmax∑é😀\u{0}\u{7f}$#@&~`'\"\\".chars().collect();
+        let mut generator = Generator { state: 0x2545F4914F6CDD1D };
+        for _ in 0..20000 {
+            let length = generator.below(24);
+            let input: String = (0..length).map(|_| pool[generator.below(pool.len())]).collect();
+            let result = std::panic::catch_unwind(|| {
+                let _ = tokenize(&input);
+                let mut scanner = Scanner::new(&input);
+                scanner.integer = true;
+                let _ = scanner.scan_tokens_recovering();
+                let _ = Scanner::with_settings(&input, &Settings { decimal_comma: true, exact: true, ..Settings::default() }).count();
+            });
+            assert!(result.is_ok(), "the scanner panicked on {:?}", input);
         }
 
-        if self.peek() == '.' && self.peek_next().is_digit(10) {
-            self.advance();
+        for input in ["1__0", "1_", "1e", "1e+", ".", "..5", "0x", "0xG", "0x1_0", "1.2.3", "9e99999", "\u{0}", "é", "😀1"].iter() {
+            let _ = tokenize(input);
+        }
+    }
 
-            while self.peek().is_digit(10) {
-                self.advance();
+    #[test]
+    fn assignments() {
+        let mut session = Session::new();
+        assert_eq!(session.evaluate("x = 3 + 2").unwrap(), 5.0);
+        assert_eq!(session.evaluate("x * 4").unwrap(), 20.0);
+        assert_eq!(session.variable("x"), Some(5.0));
+        assert_eq!(session.evaluate("y = -x").unwrap(), -5.0);
+        assert_eq!(session.evaluate("x = x + y + 1").unwrap(), 1.0);
+
+        assert!(matches!(session.evaluate("z + 1"), Err(Error::Parse(ParseError::UnknownIdentifier { .. }))));
+        assert!(session.evaluate("z = q").is_err());
+        assert_eq!(session.variable("z"), None);
+
+        assert!(matches!(session.evaluate("1 + x = 2"), Err(Error::Parse(ParseError::UnexpectedAssignment { .. }))));
+        assert!(matches!(session.evaluate("pi = 3"), Err(Error::Parse(ParseError::UnexpectedAssignment { .. }))));
+        assert!(matches!(session.evaluate("x ="), Err(Error::Parse(ParseError::EmptyExpression))));
+        assert!(evaluate_str("a = 1").is_err());
+    }
+
+    #[test]
+    fn previous_result() {
+        let mut session = Session::new();
+        let error = session.evaluate("ans + 1").unwrap_err();
+        assert!(matches!(error, Error::Parse(ParseError::NoPreviousResult { .. })));
+        assert!(error.to_string().contains("no previous result"));
+
+        assert_eq!(session.evaluate("2+2").unwrap(), 4.0);
+        assert_eq!(session.evaluate("ans * 3").unwrap(), 12.0);
+        assert_eq!(session.evaluate("ans - 2").unwrap(), 10.0);
+
+        // A failed line leaves the previous result alone, and an assignment is a result too.
+        assert!(session.evaluate("1 / 0").is_err());
+        assert_eq!(session.evaluate("ans").unwrap(), 10.0);
+        assert_eq!(session.evaluate("x = ans / 2").unwrap(), 5.0);
+        assert_eq!(session.evaluate("ans").unwrap(), 5.0);
+
+        assert!(matches!(evaluate_str("ans"), Err(Error::Parse(ParseError::NoPreviousResult { .. }))));
+    }
+
+    #[test]
+    fn validation() {
+        // Nothing is evaluated, so neither the division by zero nor the overflow is an error.
+        for input in ["1 + 2", "1 / 0", "x * y", "200!", "max(1, 2) ? -3 : 4%", "2(3)", "9 ^ 9 ^ 9 ^ 9"].iter() {
+            assert!(validate(input).is_ok(), "{}", input);
+        }
+
+        assert!(matches!(validate("(1 + 2"), Err(Error::Parse(ParseError::MismatchedLeftParen { .. }))));
+        assert!(matches!(validate("1 + 2)"), Err(Error::Parse(ParseError::MismatchedRightParen { .. }))));
+        assert!(matches!(validate("1 $ 2"), Err(Error::Scan(ScanError::UnexpectedCharacter { .. }))));
+        assert!(matches!(validate(""), Err(Error::Parse(ParseError::EmptyExpression))));
+        assert!(matches!(validate("pow(1)"), Err(Error::Parse(ParseError::WrongArgumentCount { .. }))));
+        assert!(matches!(validate("1, 2"), Err(Error::Parse(ParseError::UnexpectedComma { .. }))));
+        for input in ["1 +", "* 3", "1 2", "()", "1 ? 2"].iter() {
+            assert!(validate(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn operand_balance() {
+        let error = |input: &str| match evaluate_str(input) {
+            Err(Error::Parse(error)) => error,
+            result => panic!("{} gave {:?}", input, result),
+        };
+
+        match error("1 +") {
+            ParseError::MissingOperand { lexeme, span, .. } => assert_eq!((lexeme.as_str(), span), ("+", 2..3)),
+            other => panic!("{:?}", other),
+        }
+        match error("* 3") {
+            ParseError::UnexpectedOperator { lexeme, span, .. } => assert_eq!((lexeme.as_str(), span), ("*", 0..1)),
+            other => panic!("{:?}", other),
+        }
+        match error("1 2") {
+            ParseError::UnexpectedOperand { lexeme, span, .. } => assert_eq!((lexeme.as_str(), span), ("2", 2..3)),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(error("1 +").to_string(), "Expected an operand after + at line 1, column 3.");
+
+        for input in ["1 + * 2", ", 1", "? 1 : 2", "1 ? : 2", "−1 × ÷"].iter() {
+            assert!(matches!(error(input), ParseError::UnexpectedOperator { .. }), "{}", input);
+        }
+        for input in ["(1 +)", "max(1, )", "max(, 1)", "1 ? 2 :", "-"].iter() {
+            assert!(matches!(error(input), ParseError::MissingOperand { .. }), "{}", input);
+        }
+        for input in ["x 2", "(2) 3"].iter() {
+            assert!(matches!(error(input), ParseError::UnexpectedOperand { .. }), "{}", input);
+        }
+
+        for input in ["-(-1)", "2(3)", "5! + 50%", "max(1, -2) ? -3 : 4", "--1", "(1)(2)", "1 - -2"].iter() {
+            assert!(evaluate_str(input).is_ok(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn parser_never_panics() {
+        let pool = ["1", "2.5", "x", "pi", "+", "-", "*", "/", "//", "%", "^", "!", "(", ")", "[", "]", "|", "max", "sqrt", ",", "?", ":", "<", "==", " ", "=", "0"];
+        let mut generator = Generator { state: 0x9E3779B97F4A7C15 };
+        for _ in 0..30000 {
+            let length = generator.below(10);
+            let input: String = (0..length).map(|_| pool[generator.below(pool.len())]).collect();
+            let result = std::panic::catch_unwind(|| {
+                let _ = evaluate_str(&input);
+                let _ = parse_str(&input);
+                let _ = evaluate_int(&input);
+                let _ = to_rpn(&input);
+                let _ = validate(&input);
+                let _ = evaluate_collecting_errors(&input);
+            });
+            assert!(result.is_ok(), "the parser panicked on {:?}", input);
+        }
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(evaluate_str("1 + 2 # this is three").unwrap(), 3.0);
+        assert_eq!(evaluate_str("1 + 2#3").unwrap(), 3.0);
+        assert_eq!(tokenize("1 # 2").unwrap().len(), 1);
+        assert!(matches!(evaluate_str("# only a comment"), Err(Error::Parse(ParseError::EmptyExpression))));
+
+        // A line that is only a comment is as blank as an empty one.
+        let results = evaluate_all("# header\n1 + 1 # two\n   # indented\n\n2 * (3 # open\n4 # $ not scanned\n");
+        let lines: Vec<usize> = results.iter().map(|result| result.0).collect();
+        assert_eq!(lines, [2, 5, 6]);
+        assert_eq!(results[0].1.as_ref().ok(), Some(&2.0));
+        assert!(results[1].1.is_err());
+        assert_eq!(results[2].1.as_ref().ok(), Some(&4.0));
+
+        let mut session = Session::new();
+        assert_eq!(session.evaluate("x = 4 # four").unwrap(), 4.0);
+    }
+
+    #[test]
+    fn should_pop() {
+        use TokenType::*;
+
+        let table = PrecedenceTable::new();
+        // Higher precedence on top pops, lower does not.
+        assert!(table.should_pop(Star, Plus));
+        assert!(!table.should_pop(Plus, Star));
+        assert!(table.should_pop(Plus, Less));
+        assert!(!table.should_pop(Less, Plus));
+        assert!(table.should_pop(Caret, Star));
+        assert!(!table.should_pop(Star, Caret));
+        assert!(table.should_pop(Bang, Caret));
+
+        // Equal precedence pops only for a left-associative incoming operator.
+        assert!(table.should_pop(Plus, Minus));
+        assert!(table.should_pop(Minus, Minus));
+        assert!(!table.should_pop(Caret, Caret));
+
+        // A prefix operator or a function waits for its operand, and a group is popped only by its closing.
+        assert!(!table.should_pop(Star, UMinus));
+        assert!(!table.should_pop(Caret, Function));
+        assert!(table.should_pop(UMinus, Plus));
+        assert!(!table.should_pop(UMinus, Caret));
+        assert!(table.should_pop(Function, Caret));
+        assert!(!table.should_pop(LeftParen, Plus));
+        assert!(!table.should_pop(LeftBracket, Star));
+
+        let mut table = PrecedenceTable::new();
+        table.set(Minus, 2, Associativity::Right);
+        assert!(!table.should_pop(Plus, Minus));
+        assert!(table.should_pop(Minus, Plus));
+
+        let mut table = PrecedenceTable::new();
+        table.set(Custom('@'), 5, Associativity::Right);
+        assert!(!table.should_pop(Caret, Custom('@')));
+        assert!(!table.should_pop(Custom('@'), Caret));
+        table.set(Custom('@'), 5, Associativity::Left);
+        assert!(table.should_pop(Caret, Custom('@')));
+    }
+
+    #[test]
+    fn pow() {
+        assert_eq!(evaluate_str("pow(2, 3)").unwrap(), 8.0);
+        assert_eq!(evaluate_str("pow(2,10)").unwrap(), 1024.0);
+        assert!((evaluate_str("pow(9, 0.5)").unwrap() - 3.0).abs() < 1e-12);
+        assert_eq!(evaluate_str("pow(2, 3) == 2 ^ 3").unwrap(), 1.0);
+        assert_eq!(evaluate_str("pow(2, -1) + pow(-2, 2)").unwrap(), 4.5);
+        assert_eq!(evaluate_str("pow(2, pow(1 + 1, 2))").unwrap(), 16.0);
+
+        assert!(matches!(evaluate_str("pow(2)"), Err(Error::Parse(ParseError::WrongArgumentCount { expected: 2, found: 1, .. }))));
+        assert!(matches!(evaluate_str("pow(1, 2, 3)"), Err(Error::Parse(ParseError::WrongArgumentCount { expected: 2, found: 3, .. }))));
+    }
+
+    #[test]
+    fn clamp() {
+        assert_eq!(evaluate_str("clamp(15, 0, 10)").unwrap(), 10.0);
+        assert_eq!(evaluate_str("clamp(-3, 0, 10)").unwrap(), 0.0);
+        assert_eq!(evaluate_str("clamp(4.5, 0, 10)").unwrap(), 4.5);
+        assert_eq!(evaluate_str("clamp(5, 5, 5)").unwrap(), 5.0);
+        assert_eq!(evaluate_str("2 * clamp(1 + 1, -1, 1)").unwrap(), 2.0);
+
+        assert!(matches!(evaluate_str("clamp(1, 10, 0)"), Err(Error::Parse(ParseError::InvalidBounds { span: Range { start: 0, end: 5 }, .. }))));
+        assert!(matches!(evaluate_str("clamp(1, 0/0, 2)"), Err(Error::Parse(_))));
+        assert!(matches!(evaluate_str("clamp(1, 2)"), Err(Error::Parse(ParseError::WrongArgumentCount { expected: 3, .. }))));
+        assert_eq!(evaluate_str("1 ? 2 : clamp(1, 10, 0)").unwrap(), 2.0);
+        assert!(eval(&parse_str("clamp(1, 10, 0)").unwrap()).is_nan());
+    }
+
+    #[test]
+    fn compiled_programs() {
+        let inputs = ["1 + 2 * 3", "x ^ 2 + 1", "max(x, 3) ? -x : 50%", "x > 2 ? 1 / (x - x) : 0", "sqrt(x) * pi", "5! // x", "clamp(x, 0, 2) + log(2, 8)", "y + 1", "x ? 1 : y"];
+        for input in inputs.iter() {
+            let program = compile(input).unwrap();
+            for x in [0.0, 1.0, 2.5, 3.0, -4.0].iter() {
+                let mut bindings = HashMap::new();
+                bindings.insert(String::from("x"), *x);
+                let expected = evaluate_with(input, &bindings).map_err(|error| error.to_string());
+                let result = program.run(&bindings).map_err(|error| error.to_string());
+                match (&expected, &result) {
+                    (Ok(expected), Ok(result)) => assert!(same_value(*expected, *result), "{} with x = {}", input, x),
+                    _ => assert_eq!(expected, result, "{} with x = {}", input, x),
+                }
             }
         }
 
-        self.add_token(NUMBER, Some(self.get_float_number()));
+        assert!(compile("(1 + 2").is_err());
+        assert!(compile("1 +").is_err());
+        assert!(compile("1 / 0").unwrap().run(&HashMap::new()).is_err());
     }
 
-    fn peek(&self) -> char {
-        if self.is_at_end() {
-            return '\0';
+    #[test]
+    fn parser_state() {
+        let table = PrecedenceTable::new();
+        let tokens = tokenize("1 + 2 * 3").unwrap();
+        let mut parser = Parser::new(&tokens, RpnWriter { lexemes: vec![] }, &table);
+        assert!(!parser.is_at_end());
+        assert_eq!(parser.peek().unwrap().lexeme, "1");
+        assert!(parser.previous(0).is_none());
+
+        assert_eq!(parser.advance().lexeme, "1");
+        assert_eq!(parser.previous(0).unwrap().lexeme, "1");
+        assert!(parser.previous(1).is_none());
+        assert_eq!(parser.peek().unwrap().lexeme, "+");
+
+        parser.advance();
+        parser.advance();
+        parser.advance();
+        assert_eq!(parser.previous(2).unwrap().lexeme, "+");
+        parser.advance();
+        assert!(parser.is_at_end());
+        assert!(parser.peek().is_none());
+
+        assert_eq!(Parser::new(&tokens, RpnWriter { lexemes: vec![] }, &table).parse().unwrap(), ["1", "2", "3", "*", "+"]);
+        let tokens = tokenize("max(1, 2").unwrap();
+        assert!(matches!(Parser::new(&tokens, Validation, &table).parse(), Err(ParseError::MismatchedLeftParen { .. })));
+        assert!(matches!(Parser::new(&[], Validation, &table).parse(), Err(ParseError::EmptyExpression)));
+    }
+
+    #[test]
+    fn gcd_and_lcm() {
+        assert_eq!(evaluate_str("gcd(12, 18)").unwrap(), 6.0);
+        assert_eq!(evaluate_str("lcm(4, 6)").unwrap(), 12.0);
+        assert_eq!(evaluate_str("gcd(0, 5)").unwrap(), 5.0);
+        assert_eq!(evaluate_str("gcd(7, 0)").unwrap(), 7.0);
+        assert_eq!(evaluate_str("gcd(0, 0)").unwrap(), 0.0);
+        assert_eq!(evaluate_str("lcm(0, 5)").unwrap(), 0.0);
+        assert_eq!(evaluate_str("gcd(0.1 * 30, 9)").unwrap(), 3.0);
+        assert_eq!(evaluate_str("lcm(2^40, 3^20)").unwrap(), 2f64.powi(40) * 3f64.powi(20));
+
+        for input in ["gcd(1.5, 3)", "lcm(-4, 6)", "gcd(4, 1e300)"].iter() {
+            match evaluate_str(input) {
+                Err(Error::Parse(ParseError::NotAWholeNumber { name, span, .. })) => {
+                    assert_eq!(name, &input[..3]);
+                    assert_eq!(span, 0..3);
+                },
+                result => panic!("{}: {:?}", input, result),
+            }
         }
 
-        return self.chars[self.current];
+        assert!(evaluate_str("gcd(1.5, 3)").unwrap_err().to_string().starts_with("gcd is defined only for non-negative integers"));
+        assert!(matches!(evaluate_str("gcd(1)"), Err(Error::Parse(ParseError::WrongArgumentCount { .. }))));
+        assert!(eval(&parse_str("gcd(1.5, 3)").unwrap()).is_nan());
     }
 
-    fn peek_next(&self) -> char {
-        if self.current + 1 >= self.chars.len() {
-            return '\0';
+    #[test]
+    fn maximum_input_length() {
+        let settings = Settings { max_input_length: Some(10), ..Settings::default() };
+        assert_eq!(evaluate_with_settings("1 + 2 + 30", &settings).unwrap(), 33.0);
+        match evaluate_with_settings("1 + 2 + 3 + 4", &settings) {
+            Err(Error::Scan(ScanError::InputTooLong { length, limit, span })) => {
+                assert_eq!((length, limit), (13, 10));
+                assert_eq!(span, 10..13);
+            },
+            result => panic!("{:?}", result),
+        }
+        // The span counts characters, the limit bytes.
+        match evaluate_with_settings("ééééé + 1", &settings) {
+            Err(Error::Scan(ScanError::InputTooLong { length: 14, span, .. })) => assert_eq!(span, 5..9),
+            result => panic!("{:?}", result),
         }
 
-        return self.chars[self.current+1]
+        let long = "1+".repeat(MAX_INPUT_LENGTH / 2) + "1";
+        assert!(matches!(evaluate_str(&long), Err(Error::Scan(ScanError::InputTooLong { .. }))));
+        assert!(matches!(tokenize(&long), Err(ScanError::InputTooLong { .. })));
+        assert!(compile(&long).is_err());
+        assert!(evaluate_collecting_errors(&long).is_err());
+        assert_eq!(evaluate_all(&long).len(), 1);
+
+        let longest = "1+".repeat(MAX_INPUT_LENGTH / 2 - 1) + "1";
+        assert_eq!(evaluate_str(&longest).unwrap(), (MAX_INPUT_LENGTH / 2) as f64);
+        let unlimited = Settings { max_input_length: Some(usize::MAX), ..Settings::default() };
+        assert_eq!(evaluate_with_settings(&long, &unlimited).unwrap(), (MAX_INPUT_LENGTH / 2 + 1) as f64);
     }
 
-    // This way to make a substring is awful,
-    // but I don't know how to do it in a better way.
-    fn make_lexeme(&self) -> String {
-        let mut string = String::new();
-        for i in self.start..self.current {
-            string.push(self.chars[i]);
+    #[test]
+    fn notation() {
+        let scientific = |result: f64, precision: Option<usize>| format_result(result, &Options { notation: Notation::Scientific, precision, ..options() });
+        assert_eq!(scientific(1230000.0, None), "1.23e6");
+        assert_eq!(scientific(0.000123, None), "1.23e-4");
+        assert_eq!(scientific(1234567.0, Some(2)), "1.23e6");
+        assert_eq!(scientific(-5.0, None), "-5e0");
+
+        let engineering = |result: f64, precision: Option<usize>| format_result(result, &Options { notation: Notation::Engineering, precision, ..options() });
+        assert_eq!(engineering(12345.0, None), "12.345e3");
+        assert_eq!(engineering(0.0012, None), "1.2e-3");
+        assert_eq!(engineering(123456789.0, None), "123.456789e6");
+        assert_eq!(engineering(100.0, None), "100e0");
+        assert_eq!(engineering(1000.0, None), "1e3");
+        assert_eq!(engineering(-0.00005, None), "-50e-6");
+        assert_eq!(engineering(0.0, None), "0e0");
+        assert_eq!(engineering(12345.0, Some(1)), "12.3e3");
+        assert_eq!(engineering(12.0, Some(0)), "12e0");
+        assert_eq!(engineering(f64::INFINITY, None), "inf");
+
+        // Rounding can carry into the next group of three.
+        assert_eq!(engineering(999.96, Some(2)), "1.00e3");
+        assert_eq!(engineering(99.996, Some(1)), "100.0e0");
+
+        assert_eq!(format_result(1234.5, &options()), "1234.5");
+    }
+
+    #[test]
+    fn absolute_value_bars() {
+        assert_eq!(evaluate_str("|3 - 7|").unwrap(), 4.0);
+        assert_eq!(evaluate_str("|−5|").unwrap(), 5.0);
+        assert_eq!(evaluate_str("|1 - |2 - 5||").unwrap(), 2.0);
+        assert_eq!(evaluate_str("||-1| - 3|^2").unwrap(), 4.0);
+        assert_eq!(evaluate_str("2 * |-3| + |4|").unwrap(), 10.0);
+        assert_eq!(evaluate_str("-|-2|").unwrap(), -2.0);
+        assert_eq!(evaluate_str("|2 - 3|!").unwrap(), 1.0);
+        assert_eq!(evaluate_str("(|-2|)").unwrap(), 2.0);
+        assert_eq!(evaluate_str("max(|-1|, |-2|)").unwrap(), 2.0);
+
+        assert!(matches!(evaluate_str("|1"), Err(Error::Parse(ParseError::MismatchedLeftParen { .. }))));
+        assert!(matches!(evaluate_str("(1|"), Err(Error::Parse(ParseError::MismatchedGrouping { .. }))));
+        assert!(matches!(evaluate_str("|1, 2|"), Err(Error::Parse(ParseError::UnexpectedComma { .. }))));
+        match evaluate_str("2|3|") {
+            Err(error @ Error::Parse(ParseError::UnopenedBar { .. })) => {
+                assert!(error.to_string().starts_with("The bar closes an absolute value that is not open at line 1, column 2."));
+                assert_eq!(error.span(), Some(1..2));
+            },
+            result => panic!("{:?}", result),
         }
-        return string;
+        assert!(matches!(evaluate_collecting_errors("2|x|"), Err(errors) if matches!(errors[0], Error::Parse(ParseError::UnopenedBar { .. }))));
+        assert!(evaluate_str("||").is_err());
+
+        assert_eq!(to_infix(&parse_str("|x - 1|").unwrap()), "abs(x - 1)");
+        assert_eq!(to_rpn("|1 - 2|").unwrap().join(" "), "1 2 - abs");
+        assert_eq!(tokens_to_string(&tokenize("| 1 -| -2 || ").unwrap()), "|1 - |-2||");
+        assert!(validate("|1 - |2||").is_ok());
     }
 
-    fn get_float_number(&self) -> f64 {
-        self.make_lexeme().parse::<f64>().unwrap()
+    #[test]
+    fn worked_solution() {
+        let (result, steps) = evaluate_steps("2 + 3 * 4").unwrap();
+        assert_eq!(result, 14.0);
+        assert_eq!(steps, ["3 * 4 = 12", "2 + 12 = 14"]);
+
+        let (result, steps) = evaluate_steps("(3 + 4) * 2").unwrap();
+        assert_eq!(result, 14.0);
+        assert_eq!(steps, ["3 + 4 = 7", "7 * 2 = 14"]);
+
+        assert_eq!(evaluate_steps("2 * -3! + max(1, 50%)").unwrap().1, ["3! = 6", "2 * (-6) = -12", "50% = 0.5", "max(1, 0.5) = 1", "-12 + 1 = -11"]);
+        assert_eq!(evaluate_steps("-(-4) + -2").unwrap().1, ["-(-4) = 4", "4 + (-2) = 2"]);
+        // The branch that isn't taken adds no steps.
+        assert_eq!(evaluate_steps("1 < 2 ? |-4| : 1 / 0").unwrap().1, ["1 < 2 = 1", "abs(-4) = 4"]);
+        assert_eq!(evaluate_steps("0 ? 5 : 6").unwrap().1, ["0 ? 5 : 6 = 6"]);
+        assert!(evaluate_steps("7").unwrap().1.is_empty());
+
+        assert!(matches!(evaluate_steps("1 / 0"), Err(Error::Parse(ParseError::DivisionByZero { .. }))));
+        assert!(evaluate_steps("1 +").is_err());
     }
 
-}
+    #[cfg(feature = "rand")]
+    #[test]
+    fn random() {
+        let settings = Settings { seed: Some(42), ..Settings::default() };
+        let input = "random() + random(10, 20) * 1000";
+        assert_eq!(evaluate_with_settings(input, &settings).unwrap(), evaluate_with_settings(input, &settings).unwrap());
+        assert_ne!(evaluate_with_settings("random()", &settings).unwrap(), evaluate_with_settings("random() * 0 + random()", &settings).unwrap());
+
+        for _ in 0..100 {
+            let x = evaluate_str("random()").unwrap();
+            assert!((0.0..1.0).contains(&x));
+            let y = evaluate_str("random(-2, 3)").unwrap();
+            assert!((-2.0..3.0).contains(&y));
+        }
 
-fn parse(tokens: &Vec<Token>) -> f64 {
-    let mut current: usize = 0;
-    let mut results: Vec<f64> = vec![];
-    let mut operators: Vec<&Token> = vec![];
+        for input in ["random(2, 2)", "random(3, 1)", "random(0, 1e308 * 10)"].iter() {
+            assert!(matches!(evaluate_str(input), Err(Error::Parse(ParseError::InvalidBounds { .. }))), "{}", input);
+        }
+        assert!(matches!(evaluate_str("random(1)"), Err(Error::Parse(ParseError::WrongArgumentCount { expected: 2, found: 1, .. }))));
+        assert!(eval(&parse_str("random(3, 1)").unwrap()).is_nan());
+    }
 
-    let mut token: &Token;
-    while current < tokens.len() {
-        token = &tokens[current];
-        current += 1;
+    #[cfg(not(feature = "rand"))]
+    #[test]
+    fn random_needs_the_feature() {
+        assert!(matches!(evaluate_str("random()"), Err(Error::Scan(ScanError::UnknownFunction { .. }))));
+        assert!(matches!(evaluate_str("random"), Err(Error::Parse(ParseError::UnknownIdentifier { .. }))));
+    }
 
-        if token.ttype == NUMBER {
-            let literal = match token.literal {
-                Some(x) => x,
-                None => unreachable!("Invalid number literal. Lexeme: {}.", token.lexeme),
-            };
-            results.push(literal);
-        } else if token.ttype.precedence != 0 {
-            while
-                !operators.is_empty()
-                && operators[operators.len()-1].ttype != LEFT_PAREN
-                && operators[operators.len()-1].ttype.precedence >= token.ttype.precedence
-            {
-                let result = evaluate(operators.pop().unwrap(), &mut results);
-                results.push(result);
-            }
-            operators.push(token);
-        } else if token.ttype == LEFT_PAREN {
-            operators.push(token);
-        } else if token.ttype == RIGHT_PAREN {
-            while !operators.is_empty() && operators[operators.len()-1].ttype != LEFT_PAREN {
-                let result = evaluate(operators.pop().unwrap(), &mut results);
-                results.push(result);
+    #[test]
+    fn variadic_functions() {
+        assert_eq!(evaluate_str("sum(1,2,3,4)").unwrap(), 10.0);
+        assert_eq!(evaluate_str("avg(2,4)").unwrap(), 3.0);
+        assert_eq!(evaluate_str("max(5,1,9,2)").unwrap(), 9.0);
+        assert_eq!(evaluate_str("min(5,1,9,2)").unwrap(), 1.0);
+        assert_eq!(evaluate_str("max(1, 2)").unwrap(), 2.0);
+        assert_eq!(evaluate_str("sum(7)").unwrap(), 7.0);
+        assert_eq!(evaluate_str("max(-3)").unwrap(), -3.0);
+        assert_eq!(evaluate_str("avg(1, 2, 3, 4, 5, 6) * 2").unwrap(), 7.0);
+        assert_eq!(evaluate_str("sum(max(1, 2, 3), min(4, 5), 1 + 1)").unwrap(), 9.0);
+
+        for input in ["sum()", "avg()", "max()", "min()"].iter() {
+            match evaluate_str(input) {
+                Err(error @ Error::Parse(ParseError::WrongArgumentCount { expected: 1, found: 0, .. })) => assert!(error.to_string().contains("takes at least 1 argument")),
+                result => panic!("{}: {:?}", input, result),
             }
+        }
+        assert!(evaluate_str("pow(1)").unwrap_err().to_string().starts_with("pow takes 2 argument(s) but 1 given"));
+
+        assert_eq!(eval(&parse_str("sum(1, 2, 3)").unwrap()), 6.0);
+        assert_eq!(to_infix(&parse_str("sum(1,2,3)").unwrap()), "sum(1, 2, 3)");
+        assert_eq!(to_rpn("avg(1, 2, 3)").unwrap().join(" "), "1 2 3 avg");
+        assert_eq!(evaluate_steps("sum(1, 2, 3)").unwrap().1, ["sum(1, 2, 3) = 6"]);
+    }
+
+    #[test]
+    fn explanation() {
+        assert_eq!(explain("1 + 2 * 3").unwrap(), [
+            "Keeping + for later because * has higher precedence.",
+            "Applying * at the end.",
+            "Applying + at the end.",
+        ]);
+        assert_eq!(explain("(1 + 2) * 3").unwrap(), [
+            "Applying + inside ( ) before anything outside, because the brackets override precedence.",
+            "Applying * at the end.",
+        ]);
+        assert_eq!(explain("2 * 3 + 1").unwrap(), [
+            "Applying * before + because * has higher precedence.",
+            "Applying + at the end.",
+        ]);
+        assert_eq!(explain("5 - 2 - 1").unwrap(), [
+            "Applying - before - because they have the same precedence and group left to right.",
+            "Applying - at the end.",
+        ]);
+        assert_eq!(explain("2 ^ 3 ^ 2").unwrap(), [
+            "Keeping ^ for later because ^ groups right to left.",
+            "Applying ^ at the end.",
+            "Applying ^ at the end.",
+        ]);
+        assert_eq!(explain("1 < 2 ? max(1 + 1, 3) : 0").unwrap(), [
+            "Applying < before ? because the conditional binds loosest.",
+            "Applying + to complete the argument.",
+            "Applying : at the end.",
+        ]);
+
+        // Nothing is evaluated, so dividing by zero is fine.
+        assert_eq!(explain("1 / 0").unwrap(), ["Applying / at the end."]);
+        assert!(explain("7").unwrap().is_empty());
+        assert!(explain("1 +").is_err());
+    }
+
+    #[test]
+    fn checked_tree_evaluation() {
+        let tree = parse_str("1/(2-2)").unwrap();
+        assert_eq!(eval(&tree), f64::INFINITY);
+        assert_eq!(eval_checked(&tree), Err(EvalError::DivisionByZero));
+
+        assert_eq!(eval_checked(&parse_str("1 + 2 * 3").unwrap()), Ok(7.0));
+        assert_eq!(eval_checked(&parse_str("5 % 0").unwrap()), Err(EvalError::DivisionByZero));
+        assert_eq!(eval_checked(&parse_str("10 ^ 400").unwrap()), Err(EvalError::Overflow));
+        assert_eq!(eval_checked(&parse_str("sqrt(-4)").unwrap()), Err(EvalError::NotANumber));
+        assert_eq!(eval_checked(&parse_str("x + 1").unwrap()), Err(EvalError::UnboundVariable { name: String::from("x") }));
+        assert_eq!(eval_checked(&parse_str("1 > 0 ? 2 : 1 / 0").unwrap()), Ok(2.0));
+        assert_eq!(EvalError::DivisionByZero.to_string(), "Division by zero.");
+
+        // The remainder by zero is a division by zero on every path.
+        assert_eq!(eval_checked(&parse_str("1 + 5 % (3 - 3)").unwrap()), Err(EvalError::DivisionByZero));
+        assert!(matches!(evaluate_str("1 + 5 % (3 - 3)"), Err(Error::Parse(ParseError::DivisionByZero { .. }))));
+
+        // A tree with an infinity in it prints as something that parses back.
+        for (number, infix) in [(f64::INFINITY, "(1 / 0)"), (f64::NEG_INFINITY, "(-1 / 0)"), (f64::NAN, "(0 / 0)")].iter() {
+            let tree = Expr::BinaryOp { op: TokenType::Star, left: Box::new(Expr::Number(2.0)), right: Box::new(Expr::Number(*number)) };
+            assert_eq!(to_infix(&tree), format!("2 * {}", infix));
+            assert!(same_value(eval(&parse_str(&to_infix(&tree)).unwrap()), eval(&tree)));
+            assert_eq!(eval_checked(&parse_str(&to_infix(&tree)).unwrap()), Err(EvalError::DivisionByZero));
+        }
+    }
+
+    #[test]
+    fn whole_results() {
+        let format = |result: f64, precision: Option<usize>| format_result(result, &Options { precision, ..options() });
+        assert_eq!(format(6.0, None), "6");
+        assert_eq!(format(6.0, Some(2)), "6");
+        assert_eq!(format(-6.0, Some(2)), "-6");
+        assert_eq!(format(2.5, None), "2.5");
+        assert_eq!(format(10.0 / 3.0, Some(2)), "3.33");
+        assert_eq!(format(5.999, Some(2)), "6.00");
+        assert_eq!(format(0.5, Some(0)), "0");
+        assert_eq!(format(f64::INFINITY, Some(2)), "inf");
+    }
+
+    #[test]
+    fn match_char() {
+        let mut scanner = Scanner::new("<");
+        scanner.advance();
+        assert!(scanner.is_at_end());
+        assert!(!scanner.match_char('='));
+        assert!(!scanner.match_char('\0'));
+        assert_eq!(scanner.current, 1);
+
+        let mut scanner = Scanner::new("<=1");
+        scanner.advance();
+        assert!(!scanner.match_char('<'));
+        assert_eq!(scanner.current, 1);
+        assert!(scanner.match_char('='));
+        assert_eq!((scanner.current, scanner.column), (2, 3));
+
+        let types: Vec<TokenType> = tokenize("1<=2>=3==4!=5//6<7>8/9").unwrap().iter().map(|token| token.ttype).collect();
+        assert_eq!(types, [
+            TokenType::Number, TokenType::LessEqual, TokenType::Number, TokenType::GreaterEqual, TokenType::Number,
+            TokenType::EqualEqual, TokenType::Number, TokenType::BangEqual, TokenType::Number, TokenType::DoubleSlash,
+            TokenType::Number, TokenType::Less, TokenType::Number, TokenType::Greater, TokenType::Number,
+            TokenType::Slash, TokenType::Number,
+        ]);
+        assert_eq!(tokenize("1 <").unwrap().last().unwrap().ttype, TokenType::Less);
+        assert_eq!(tokenize("x =").unwrap().last().unwrap().ttype, TokenType::Equal);
+    }
+
+    #[test]
+    fn double_star() {
+        assert_eq!(evaluate_str("2 ** 10").unwrap(), 1024.0);
+        assert_eq!(evaluate_str("2*3").unwrap(), 6.0);
+        assert_eq!(evaluate_str("3 * 2 ** 2").unwrap(), 12.0);
+        assert_eq!(evaluate_str("-2**2").unwrap(), -4.0);
+        assert_eq!(evaluate_str("2**-1").unwrap(), 0.5);
+
+        // Right associative, the same as ^.
+        assert_eq!(evaluate_str("2**3**2").unwrap(), 512.0);
+        assert_eq!(evaluate_str("2**3**2").unwrap(), evaluate_str("2^3^2").unwrap());
+        assert_eq!(evaluate_str("2 ** 3 ^ 2").unwrap(), 512.0);
+
+        assert!(evaluate_str("2 * * 3").is_err());
+        assert!(evaluate_str("2***3").is_err());
+
+        let tokens = tokenize("2**3").unwrap();
+        assert_eq!((tokens[1].ttype, tokens[1].lexeme), (TokenType::Caret, "**"));
+        assert_eq!(to_infix(&parse_str("2 ** x").unwrap()), "2 ^ x");
+    }
+
+    #[test]
+    fn tight_minus() {
+        assert_eq!(evaluate_str("-3^2").unwrap(), -9.0);
+        assert_eq!(evaluate_str("-2^2").unwrap(), -4.0);
+        assert_eq!(evaluate_str("-sqrt(4)^2").unwrap(), -4.0);
+
+        let settings = Settings { tight_minus: true, ..Settings::default() };
+        let evaluate = |input: &str| evaluate_with_settings(input, &settings).unwrap();
+        assert_eq!(evaluate("-3^2"), 9.0);
+        assert_eq!(evaluate("-2^2"), 4.0);
+        assert_eq!(evaluate("-2^3"), -8.0);
+        assert_eq!(evaluate("-(3)^2"), 9.0);
+        assert_eq!(evaluate("-sqrt(4)^2"), 4.0);
+        assert_eq!(evaluate("2^-3^2"), 512.0);
+        assert_eq!(evaluate("2 - -3^2"), -7.0);
+        assert_eq!(evaluate("1 - 2^2"), -3.0);
+
+        // Only ^ changes, the other operators bind as before.
+        assert_eq!(evaluate("-3!"), -6.0);
+        assert_eq!(evaluate("-2 * 3"), -6.0);
+        assert_eq!(evaluate("-50%"), -0.5);
 
-            if operators.is_empty() {
-                panic!("Mismatched right paren at position {}.", token.start_at);
+        let settings = Settings { tight_minus: true, calculator: true, ..Settings::default() };
+        assert_eq!(evaluate_with_settings("-3^2 + 1", &settings).unwrap(), 10.0);
+    }
+
+    #[test]
+    fn finite_results() {
+        let settings = Settings { finite: true, ..Settings::default() };
+        assert!(evaluate_str("1e308 * 10").unwrap().is_infinite());
+        match evaluate_with_settings("1e308 * 10", &settings) {
+            Err(Error::Parse(ParseError::Overflow { position, span })) => {
+                assert_eq!(position, Position { line: 1, column: 7 });
+                assert_eq!(span, 6..7);
+            },
+            result => panic!("{:?}", result),
+        }
+        assert!(matches!(evaluate_with_settings("2 ^ 5000 - 1", &settings), Err(Error::Parse(ParseError::Overflow { .. }))));
+
+        match evaluate_with_settings("0.0 / 0.0", &settings) {
+            Err(error @ Error::Parse(ParseError::NotANumber { .. })) => assert_eq!(error.to_string(), "/ does not give a number at line 1, column 5."),
+            result => panic!("{:?}", result),
+        }
+        assert!(matches!(evaluate_with_settings("0 % 0", &settings), Err(Error::Parse(ParseError::NotANumber { .. }))));
+        assert!(matches!(evaluate_with_settings("1 / 0", &settings), Err(Error::Parse(ParseError::DivisionByZero { .. }))));
+        assert!(matches!(evaluate_str("0.0 / 0.0"), Err(Error::Parse(ParseError::DivisionByZero { .. }))));
+
+        // An infinity that is already there is not an overflow, and neither is a branch that isn't taken.
+        assert_eq!(evaluate_with_settings("1e308 + 1 < 2", &settings).unwrap(), 0.0);
+        assert_eq!(evaluate_with_settings("0 ? 1e308 * 10 : 3", &settings).unwrap(), 3.0);
+    }
+
+    #[test]
+    fn radix_literals() {
+        assert_eq!(evaluate_str("0b1010").unwrap(), 10.0);
+        assert_eq!(evaluate_str("0o17").unwrap(), 15.0);
+        assert_eq!(evaluate_str("0x1F").unwrap(), 31.0);
+        assert_eq!(evaluate_str("0b10 + 0o10 + 0x10").unwrap(), 26.0);
+        assert_eq!(evaluate_str("0B11 + 0O7 + 0Xa").unwrap(), 20.0);
+        assert_eq!(evaluate_str("-0b1").unwrap(), -1.0);
+        assert_eq!(evaluate_str("2*0o10").unwrap(), 16.0);
+        assert_eq!(evaluate_int("0b111 * 0x2").unwrap(), 14);
+
+        for input in ["0b", "0o"].iter() {
+            assert!(matches!(evaluate_str(input), Err(Error::Scan(ScanError::MissingDigits { .. }))), "{}", input);
+        }
+        for input in ["0b102", "0b2", "0o8", "0o78"].iter() {
+            match evaluate_str(input) {
+                Err(Error::Scan(ScanError::InvalidNumber { lexeme, span, .. })) => {
+                    assert_eq!(lexeme, *input);
+                    assert_eq!(span, 0..input.len());
+                },
+                result => panic!("{}: {:?}", input, result),
             }
+        }
+    }
+
+    #[test]
+    fn hexadecimal_literals() {
+        assert_eq!(evaluate_str("0x10 * 2").unwrap(), 32.0);
+        assert_eq!(evaluate_str("0xFF + 1").unwrap(), 256.0);
+        assert_eq!(evaluate_str("0X1f").unwrap(), 31.0);
+
+        match evaluate_str("0x") {
+            Err(error @ Error::Scan(ScanError::MissingDigits { .. })) => assert!(error.to_string().contains("line 1, column 1")),
+            result => panic!("{:?}", result),
+        }
+        assert!(matches!(evaluate_str("0x+1"), Err(Error::Scan(ScanError::MissingDigits { .. }))));
+        assert!(matches!(evaluate_str("0x11111111111111111"), Err(Error::Scan(ScanError::InvalidNumber { .. }))));
+    }
+
+    #[test]
+    fn angle_conversions() {
+        let close = |input: &str, expected: f64| {
+            let result = evaluate_str(input).unwrap();
+            assert!((result - expected).abs() < 1e-9, "{} = {}", input, result);
+        };
+        close("rad2deg(deg2rad(90))", 90.0);
+        close("deg2rad(180)", consts::PI);
+        close("rad2deg(pi)", 180.0);
+        close("deg2rad(-45) * 4", -consts::PI);
+        close("sin(deg2rad(90))", 1.0);
+
+        // They convert whatever the degrees setting is.
+        let settings = Settings { degrees: true, ..Settings::default() };
+        assert!((evaluate_with_settings("deg2rad(90)", &settings).unwrap() - consts::FRAC_PI_2).abs() < 1e-12);
+        assert!(matches!(evaluate_str("deg2rad()"), Err(Error::Parse(ParseError::WrongArgumentCount { .. }))));
+    }
 
-            operators.pop();
+    #[test]
+    fn not_a_number() {
+        match evaluate_str("1 + sqrt(-4)") {
+            Err(Error::Parse(ParseError::NotANumber { lexeme, span, .. })) => {
+                assert_eq!(lexeme, "sqrt");
+                assert_eq!(span, 4..8);
+            },
+            result => panic!("{:?}", result),
         }
+        assert!(matches!(evaluate_str("sqrt(-4)"), Err(Error::Parse(ParseError::NotANumber { .. }))));
+        assert!(matches!(evaluate_str("(-8) ^ 0.5"), Err(Error::Parse(ParseError::NotANumber { .. }))));
+        assert!(matches!(evaluate_str("ln(-1)"), Err(Error::Parse(ParseError::NotANumber { .. }))));
+
+        let settings = Settings { allow_nan: true, ..Settings::default() };
+        assert!(evaluate_with_settings("sqrt(-4)", &settings).unwrap().is_nan());
+        assert!(evaluate_with_settings("1 + sqrt(-4)", &settings).unwrap().is_nan());
+        assert!(evaluate_with_settings("(-8) ^ 0.5", &settings).unwrap().is_nan());
+        assert_eq!(evaluate_with_settings("sqrt(4)", &settings).unwrap(), 2.0);
     }
 
-    while !operators.is_empty() {
-        let operator = operators.pop().unwrap();
-        if operator.ttype == LEFT_PAREN {
-            panic!("Mismatched left paren at position {}.", operator.start_at);
+    #[test]
+    fn logarithms() {
+        let close = |input: &str, expected: f64| {
+            let result = evaluate_str(input).unwrap();
+            assert!((result - expected).abs() < 1e-12, "{} = {}", input, result);
+        };
+        close("ln(e)", 1.0);
+        close("log10(1000)", 3.0);
+        close("log(2, 8)", 3.0);
+        close("log(2, log(3, 9) * 4) + 1", 4.0);
+        close("log(10, 100)^2", 4.0);
+
+        match evaluate_str("log(8)") {
+            Err(Error::Parse(ParseError::WrongArgumentCount { name, expected: 2, found: 1, .. })) => assert_eq!(name, "log"),
+            result => panic!("{:?}", result),
         }
+        assert!(matches!(evaluate_str("ln(1, 2)"), Err(Error::Parse(ParseError::WrongArgumentCount { expected: 1, found: 2, .. }))));
+        assert!(matches!(evaluate_str("log10()"), Err(Error::Parse(ParseError::WrongArgumentCount { found: 0, .. }))));
+        assert!(matches!(evaluate_str("log(2, [1, 8])"), Err(Error::Parse(ParseError::UnexpectedComma { .. }))));
+    }
 
-        let result = evaluate(operator, &mut results);
-        results.push(result);
+    #[test]
+    fn max_and_min() {
+        assert_eq!(evaluate_str("max(3, 7)").unwrap(), 7.0);
+        assert_eq!(evaluate_str("min(3, 7)").unwrap(), 3.0);
+        assert_eq!(evaluate_str("max(1, min(5, 2))").unwrap(), 2.0);
+        assert_eq!(evaluate_str("max(2 + 3, 4 * 2)").unwrap(), 8.0);
+        assert_eq!(evaluate_str("-max(-1, -2) * min(max(1, 2), 3)^2").unwrap(), 4.0);
+
+        assert!(matches!(evaluate_str("max()"), Err(Error::Parse(ParseError::WrongArgumentCount { found: 0, .. }))));
+        assert!(matches!(evaluate_str("pow(1, 2, 3)"), Err(Error::Parse(ParseError::WrongArgumentCount { expected: 2, found: 3, .. }))));
+        assert!(matches!(evaluate_str("1, 2"), Err(Error::Parse(ParseError::UnexpectedComma { .. }))));
+        assert!(matches!(evaluate_str("(1, 2)"), Err(Error::Parse(ParseError::UnexpectedComma { .. }))));
     }
 
-    if results.len() != 1 {
-        panic!("Cannot evaluate the expression to the concrete value.");
+    #[test]
+    fn conditionals() {
+        assert_eq!(evaluate_str("1 ? 2 : 3").unwrap(), 2.0);
+        assert_eq!(evaluate_str("0 ? 2 : 3").unwrap(), 3.0);
+        assert_eq!(evaluate_str("3 > 2 ? 10 : 20").unwrap(), 10.0);
+        assert_eq!(evaluate_str("1 + 1 ? -1 : 1").unwrap(), -1.0);
+        assert_eq!(evaluate_str("0 ? 1 : 2 + 1").unwrap(), 3.0);
+        assert_eq!(evaluate_str("2 * (1 ? 3 : 4)").unwrap(), 6.0);
+
+        // Nested in either branch.
+        assert_eq!(evaluate_str("1 ? 0 ? 4 : 5 : 6").unwrap(), 5.0);
+        assert_eq!(evaluate_str("0 ? 0 ? 4 : 5 : 6").unwrap(), 6.0);
+        assert_eq!(evaluate_str("0 ? 2 : 1 ? 3 : 4").unwrap(), 3.0);
+        assert_eq!(evaluate_str("0 ? 2 : 0 ? 3 : 4").unwrap(), 4.0);
+
+        // The branch that is not taken is not evaluated.
+        assert_eq!(evaluate_str("0 ? 1/0 : 2").unwrap(), 2.0);
+        assert_eq!(evaluate_str("1 ? 7 : (-1)!").unwrap(), 7.0);
+        assert_eq!(evaluate_str("1 ? 7 : y").unwrap(), 7.0);
+        assert_eq!(evaluate_int("0 ? 1 / 0 : 9").unwrap(), 9);
+        assert!(matches!(evaluate_str("1 ? 1/0 : 2"), Err(Error::Parse(ParseError::DivisionByZero { .. }))));
+
+        for input in ["1 ? 2", "1 : 2", "(1 ? 2) : 3", "1 ? (2 : 3)"].iter() {
+            assert!(matches!(evaluate_str(input), Err(Error::Parse(ParseError::MismatchedConditional { .. }))), "{}", input);
+        }
     }
 
-    return results[0];
-}
+    #[test]
+    fn floor_division() {
+        assert_eq!(evaluate_str("7 // 2").unwrap(), 3.0);
+        assert_eq!(evaluate_str("-7 // 2").unwrap(), -4.0);
+        assert_eq!(evaluate_str("7 // -2").unwrap(), -4.0);
+        assert_eq!(evaluate_str("7.5 // 2").unwrap(), 3.0);
+        assert_eq!(evaluate_str("-0.5 // 0.2").unwrap(), -3.0);
+        assert_eq!(evaluate_str("2 * 7 // 2").unwrap(), 7.0);
 
-fn evaluate(operator: &Token, results: &mut Vec<f64>) -> f64 {
-    let operand = results.pop().unwrap();
+        assert_eq!(evaluate_int("-7 // 2").unwrap(), -4);
+        assert_eq!(evaluate_int("-8 // 2").unwrap(), -4);
+
+        assert!(matches!(evaluate_str("1 // 0"), Err(Error::Parse(ParseError::DivisionByZero { .. }))));
+        assert!(matches!(evaluate_int("1 // 0"), Err(Error::Parse(ParseError::DivisionByZero { .. }))));
+    }
 
-    if operator.ttype == PLUS {
-        return results.pop().unwrap() + operand;
+    #[test]
+    fn implicit_multiplication() {
+        assert_eq!(evaluate_str("(1+1)(2+2)").unwrap(), 8.0);
+        assert_eq!(evaluate_str("2(3)").unwrap(), 6.0);
+        assert_eq!(evaluate_str("2(3+4)").unwrap(), 14.0);
+        assert_eq!(evaluate_str("1+2(3)").unwrap(), 7.0);
+        assert_eq!(evaluate_str("2^2(3)").unwrap(), 12.0);
+        assert_eq!(evaluate_str("-2(3)").unwrap(), -6.0);
+        assert_eq!(evaluate_str("2pi").unwrap(), 2.0 * consts::PI);
+        assert_eq!(evaluate_str("(1+1)pi").unwrap(), 2.0 * consts::PI);
+        assert_eq!(evaluate_str("2 sqrt(4)").unwrap(), 4.0);
+        assert_eq!(evaluate_str("sqrt(4)(2)").unwrap(), 4.0);
     }
 
-    if operator.ttype == MINUS {
-        return results.pop().unwrap() - operand;
+    #[test]
+    fn minus_around_functions() {
+        assert_eq!(evaluate_str("2-sqrt(4)").unwrap(), 0.0);
+        assert_eq!(evaluate_str("-sqrt(4)").unwrap(), -2.0);
+        assert_eq!(evaluate_str("-(1+2)").unwrap(), -3.0);
+        assert_eq!(evaluate_str("-3^2").unwrap(), -9.0);
+        assert_eq!(evaluate_str("sqrt(4)-1").unwrap(), 1.0);
+        assert_eq!(evaluate_str("max(1,-2)-3").unwrap(), -2.0);
+        assert_eq!(evaluate_str("2*-sqrt(4)").unwrap(), -4.0);
+
+        let types = |input: &str| -> Vec<TokenType> { tokenize(input).unwrap().iter().map(|token| token.ttype).collect() };
+        assert_eq!(types("2-sqrt(4)")[1], TokenType::Minus);
+        assert_eq!(types("-sqrt(4)")[0], TokenType::UMinus);
+        assert_eq!(types("sqrt(4)-1")[4], TokenType::Minus);
+        assert_eq!(types("2 - -sqrt(4)")[1..3], [TokenType::Minus, TokenType::UMinus]);
+        assert_eq!(types("max(-1, 2)")[2], TokenType::UMinus);
     }
 
-    if operator.ttype == UMINUS {
-        return -operand;
+    #[test]
+    fn functions() {
+        assert_eq!(evaluate_str("sqrt(16)").unwrap(), 4.0);
+        assert_eq!(evaluate_str("sqrt(9) + 1").unwrap(), 4.0);
+        assert_eq!(evaluate_str("sqrt(sqrt(16))").unwrap(), 2.0);
+        assert_eq!(evaluate_str("sin(0)").unwrap(), 0.0);
+        assert!((evaluate_str("cos(3.14159)").unwrap() + 1.0).abs() < 1e-5);
+
+        match evaluate_str("foo(1)") {
+            Err(error @ Error::Scan(ScanError::UnknownFunction { .. })) => assert_eq!(error.to_string(), "Unknown function: foo at line 1, column 1."),
+            result => panic!("{:?}", result),
+        }
+        assert!(matches!(evaluate_str("2 * foo(1)"), Err(Error::Scan(ScanError::UnknownFunction { .. }))));
+        assert!(matches!(evaluate_str("sqrt 4"), Err(Error::Parse(ParseError::MissingFunctionParen { .. }))));
     }
 
-    if operator.ttype == STAR {
-        return results.pop().unwrap() * operand;
+    #[test]
+    fn boxed_errors() {
+        fn run(input: &str) -> Result<f64, Box<dyn error::Error>> {
+            let result = evaluate_str(input)?;
+            Ok(result * 2.0)
+        }
+
+        assert_eq!(run("1 + 2").unwrap(), 6.0);
+
+        let error = run("1 / 0").unwrap_err();
+        assert_eq!(error.to_string(), "Division by zero at line 1, column 3.");
+        assert_eq!(error.source().unwrap().to_string(), error.to_string());
+        assert!(error.downcast_ref::<Error>().is_some());
+
+        let error = run("2 $ 1").unwrap_err();
+        assert!(error.to_string().starts_with("Unexpected character"));
+        assert!(error.source().unwrap().downcast_ref::<ScanError>().is_some());
+
+        let error: Box<dyn error::Error> = Box::new(from_json("{").unwrap_err());
+        assert!(!error.to_string().is_empty());
     }
 
-    if operator.ttype == SLASH {
-        return results.pop().unwrap() / operand;
+    #[test]
+    fn leading_blank_lines() {
+        assert_eq!(evaluate_str("\n\n1 + 2").unwrap(), 3.0);
+        assert_eq!(evaluate_str("\n1 + 2").unwrap(), 3.0);
+        assert_eq!(evaluate_str("\n\n  \n\t\r\n2 * 3\n4").unwrap(), 6.0);
+        assert_eq!(evaluate_str("# c\n\n5").unwrap(), 5.0);
+        assert_eq!(parse_str("\n  x").unwrap(), Expr::Variable(String::from("x")));
+
+        // The position still points at the line the error is on.
+        match evaluate_str("\n\n1 / 0") {
+            Err(error @ Error::Parse(ParseError::DivisionByZero { .. })) => {
+                assert_eq!(error.to_string(), "Division by zero at line 3, column 3.");
+                assert_eq!(error.span(), Some(4..5));
+            },
+            result => panic!("{:?}", result),
+        }
     }
 
-    unreachable!("Invalid token type (at pos {}..{}) when an operator expected.", operator.start_at, operator.end_at);
-}
+    #[test]
+    fn cached_evaluation() {
+        for seed in 0..2000 {
+            let mut generator = Generator { state: seed };
+            let tree = generator.expr(5);
+            assert!(same_value(eval_cached(&tree), eval(&tree)), "seed {}: {}", seed, to_infix(&tree));
 
-fn get_source() -> String {
-    match fs::read_to_string("expression.txt") {
-        Err(why) => panic!("Couldn't read: {}", why),
-        Ok(data) => data,
+            // The same subtree twice, and a cache that has seen the tree before.
+            let copy = from_json(&to_json(&tree)).unwrap();
+            let twice = Expr::BinaryOp { op: TokenType::Minus, left: Box::new(tree), right: Box::new(copy) };
+            let mut cache = SubtreeCache::new();
+            assert!(same_value(cache.eval(&twice), eval(&twice)), "seed {}: {}", seed, to_infix(&twice));
+            assert!(same_value(cache.eval(&twice), eval(&twice)), "seed {}: {}", seed, to_infix(&twice));
+        }
+
+        let sum = parse_str("170! / 169! + 170! / 169!").unwrap();
+        let term = parse_str("170! / 169!").unwrap();
+        let mut cache = SubtreeCache::new();
+        assert_eq!(cache.eval(&sum), 340.0);
+        assert_eq!(cache.len(), 6);
+        assert_eq!(cache.eval(&term), 170.0);
+        assert_eq!(cache.len(), 6);
+        // 0 and -0 are equal, but not the same subtree.
+        assert_eq!(eval_cached(&parse_str("1 / 0 - 1 / -0").unwrap()), f64::INFINITY);
+
+        // The branch that is not taken is never computed.
+        let conditional = parse_str("0 ? 170! : 1").unwrap();
+        let mut cache = SubtreeCache::new();
+        assert_eq!(cache.eval(&conditional), 1.0);
+        assert_eq!(cache.values.iter().filter(|value| value.is_some()).count(), 3);
     }
-}
 
-fn main() {
-    let source: String = get_source();
+    #[cfg(feature = "rand")]
+    #[test]
+    fn cached_random() {
+        // Two calls of random() are two numbers, however identical they look.
+        let tree = parse_str("random() - random()").unwrap();
+        assert_ne!(eval_cached(&tree), 0.0);
+        let (one, other) = (parse_str("random()").unwrap(), parse_str("random()").unwrap());
+        let mut cache = SubtreeCache::new();
+        let first = cache.eval(&one);
+        assert_ne!(cache.eval(&other), first);
+        assert_ne!(cache.eval(&one), first);
+    }
+
+    #[test]
+    fn float_functions() {
+        let specials = [
+            0.0, -0.0, f64::INFINITY, f64::NEG_INFINITY, f64::NAN, 0.5, -0.5, 1.5, -2.5, 2.5, 0.49999999999999994, 0.1,
+            1.0, -1.0, 2.0, -3.0, -8.0, 10.0, 64.0, -65.0, 1e300, -1e300, 5e-324, f64::MIN_POSITIVE, f64::MAX, 4503599627370495.5, 1e16,
+        ];
+        // Numbers of every magnitude from random bits, and numbers of a sensible one from a mantissa and an exponent.
+        let mut generator = Generator { state: 70 };
+        let mut numbers = specials.to_vec();
+        for _ in 0..20000 {
+            let bits = (generator.below(1 << 32) as u64) << 32 | generator.below(1 << 32) as u64;
+            numbers.push(f64::from_bits(bits));
+            let number = generator.below(1 << 30) as f64 / (1 << 30) as f64 * 10f64.powi(generator.below(12) as i32 - 6);
+            numbers.push(if generator.below(2) == 0 { number } else { -number });
+        }
 
-    let mut scanner = Scanner::new(source.chars().collect());
-    let tokens = scanner.scan_tokens();
+        let close = |ours: f64, theirs: f64, ulps: f64| {
+            same_value(ours, theirs) || (theirs.is_finite() && (ours - theirs).abs() <= ulps * f64::EPSILON * theirs.abs())
+        };
+        for &x in numbers.iter() {
+            let exact = [
+                ("trunc", float::trunc(x), x.trunc()), ("fract", float::fract(x), x.fract()), ("floor", float::floor(x), x.floor()),
+                ("ceil", float::ceil(x), x.ceil()), ("round", float::round(x), x.round()), ("sqrt", float::sqrt(x), x.sqrt()),
+            ];
+            for (name, ours, theirs) in exact.iter() {
+                assert!(same_value(*ours, *theirs), "{}({:e}) is {:e} instead of {:e}", name, x, ours, theirs);
+            }
 
-    // for token in tokens {
-    //     println!("{:?}", token);
-    // }
+            let mut near = vec![("ln", float::ln(x), x.ln()), ("log10", float::log10(x), x.log10()), ("exp", float::exp(x), x.exp())];
+            if x.abs() < 1e6 {
+                near.extend([("sin", float::sin(x), x.sin()), ("cos", float::cos(x), x.cos()), ("tan", float::tan(x), x.tan())].iter());
+            }
+            for (name, ours, theirs) in near.iter() {
+                assert!(close(*ours, *theirs, 4.0), "{}({:e}) is {:e} instead of {:e}", name, x, ours, theirs);
+            }
+        }
 
-    println!("Result: {:.2}", parse(tokens));
+        // A power loses about |y * ln(x)| ulps, squaring far fewer.
+        for &x in specials.iter() {
+            for &y in specials.iter().chain([1.0 / 3.0, 2.0 * 2f64.powi(52) + 2.0].iter()) {
+                let ulps = 2.0 * (y * x.abs().ln()).abs().max(32.0);
+                assert!(close(float::powf(x, y), x.powf(y), ulps), "{:e} ^ {:e} is {:e} instead of {:e}", x, y, float::powf(x, y), x.powf(y));
+            }
+        }
+        for _ in 0..20000 {
+            let x = generator.below(1 << 30) as f64 / (1 << 20) as f64 + 1e-3;
+            let whole = generator.below(129) as f64 - 64.0;
+            let y = generator.below(1 << 30) as f64 / (1 << 25) as f64 - 16.0;
+            assert!(close(float::powf(x, whole), x.powf(whole), 32.0), "{:e} ^ {:e}", x, whole);
+            assert!(close(float::powf(x, y), x.powf(y), 2.0 * (y * x.ln()).abs().max(1.0)), "{:e} ^ {:e}", x, y);
+            assert!(close(float::log(x, y.abs() + 1.5), x.log(y.abs() + 1.5), 4.0), "log({:e}, {:e})", x, y.abs() + 1.5);
+        }
+        assert_eq!(float::powf(2.0, 10.0), 1024.0);
+        assert_eq!(float::powf(10.0, 15.0), 1e15);
+        assert_eq!(float::log10(1000.0), 3.0);
+        assert_eq!(float::log10(0.001), -3.0);
+    }
 }
@@ -0,0 +1,136 @@
+// The command line as a user runs it. There is no Cargo manifest to build the binary with,
+// so the tests build it themselves. Run them from the root of the repository:
+// rustc --test tests/cli.rs -o cli && ./cli
+
+use std::env;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output, Stdio};
+use std::sync::Once;
+
+static BUILD: Once = Once::new();
+
+fn root() -> PathBuf {
+    Path::new(file!()).parent().unwrap().join("..")
+}
+
+fn binary() -> PathBuf {
+    let binary = env::temp_dir().join(format!("shunting_yard_cli_{}", process::id()));
+    BUILD.call_once(|| {
+        let rustc = env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+        let status = Command::new(rustc).arg(root().join("shunting_yard.rs")).arg("-o").arg(&binary).status().unwrap();
+        assert!(status.success());
+    });
+
+    binary
+}
+
+// A directory without expression.txt, so the expression comes from the arguments or the standard input.
+fn empty_dir() -> PathBuf {
+    let dir = env::temp_dir().join(format!("shunting_yard_cli_{}_dir", process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(binary()).args(args).current_dir(empty_dir())
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    // The binary may be done before it reads the input, e.g. when the expression is an argument. Then the pipe is closed.
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn reads_the_standard_input() {
+    let output = run(&[], "1+2\n2*3\n");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "Result: 3\nResult: 6\n");
+}
+
+#[test]
+fn takes_the_expression_as_an_argument() {
+    let output = run(&["4*5"], "1+1\n");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "Result: 20\n");
+}
+
+#[test]
+fn reads_the_expression_file() {
+    let output = Command::new(binary()).current_dir(root()).stdin(Stdio::null()).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "Result: -56\n");
+}
+
+#[test]
+fn exit_codes() {
+    let output = run(&["1 / 0"], "");
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Division by zero at line 1, column 3.\n");
+
+    assert_eq!(run(&[], "2 +\n").status.code(), Some(65));
+    assert_eq!(run(&["--precision", "many", "1"], "").status.code(), Some(64));
+}
+
+#[test]
+fn assigns_variables_in_the_repl() {
+    let output = run(&["--repl"], "x = 3 + 2\nx * 4\nw\n");
+    assert_eq!(stdout(&output), "> x = 5\n> Result: 20\n> > \n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown identifier: w"));
+}
+
+#[test]
+fn keeps_the_previous_result_in_the_repl() {
+    let output = run(&["--repl"], "ans\n2+2\nans * 3\n");
+    assert_eq!(stdout(&output), "> > Result: 4\n> Result: 12\n> \n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no previous result"));
+}
+
+#[test]
+fn prints_json() {
+    let output = run(&["--json", "3 + 4"], "");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "{\"input\": \"3 + 4\", \"result\": 7.0, \"tokens\": 3}\n");
+
+    // The quote in the message is escaped.
+    let output = run(&["--json", "1 + \"2"], "");
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(stdout(&output), "{\"error\": \"Unexpected character: \\\" at line 1, column 5.\", \"position\": 4}\n");
+
+    let output = run(&["--json", "--int", "7 / 2\n1 / 0\n# x\nx = 2"], "");
+    let lines: Vec<String> = stdout(&output).lines().map(String::from).collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "{\"input\": \"7 / 2\", \"result\": 3, \"tokens\": 3}");
+    assert!(lines[1].starts_with("{\"error\": \"Division by zero"));
+    assert!(lines[2].starts_with("{\"error\": ") && lines[2].contains("\"position\": "));
+
+    assert!(stdout(&run(&["--json", "--allow-nan", "sqrt(-1)"], "")).contains("\"result\": null"));
+    assert!(stdout(&run(&["--json", "  "], "")).contains("\"position\": null"));
+}
+
+#[test]
+fn explains_the_precedence() {
+    let output = run(&["--explain", "(1 + 2) * 3"], "");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "Applying + inside ( ) before anything outside, because the brackets override precedence.\nApplying * at the end.\n");
+}
+
+#[test]
+fn prints_whole_results_without_decimals() {
+    assert_eq!(stdout(&run(&["--precision", "2", "10 / 5"], "")), "Result: 2\n");
+    assert_eq!(stdout(&run(&["--precision", "2", "10 / 4"], "")), "Result: 2.50\n");
+}
+
+#[test]
+fn tight_minus() {
+    assert_eq!(stdout(&run(&["-3^2"], "")), "Result: -9\n");
+    assert_eq!(stdout(&run(&["--tight-minus", "-3^2"], "")), "Result: 9\n");
+}
+
+#[test]
+fn reads_the_minimum_integer() {
+    assert_eq!(stdout(&run(&["--int", "-9223372036854775808"], "")), "Result: -9223372036854775808\n");
+}
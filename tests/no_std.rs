@@ -0,0 +1,96 @@
+// The library without the standard library: the scanner, the parser and the evaluation on alloc alone.
+// There is no Cargo manifest and no embedded target here, so the tests build it for the host with the
+// no_std feature. Run them from the root of the repository:
+// rustc --test tests/no_std.rs -o no_std && ./no_std
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output};
+use std::sync::Once;
+
+static BUILD: Once = Once::new();
+
+fn root() -> PathBuf {
+    Path::new(file!()).parent().unwrap().join("..")
+}
+
+fn rustc() -> Command {
+    Command::new(env::var("RUSTC").unwrap_or_else(|_| String::from("rustc")))
+}
+
+fn dir() -> PathBuf {
+    env::temp_dir().join(format!("shunting_yard_no_std_{}", process::id()))
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+// The library, built once. The build itself has to be silent: a warning would be a leftover of std.
+fn library() -> PathBuf {
+    let library = dir().join("libshunting_yard.rlib");
+    BUILD.call_once(|| {
+        fs::create_dir_all(dir()).unwrap();
+        let output = rustc().args(["--crate-type", "lib", "--crate-name", "shunting_yard", "--cfg", "feature=\"no_std\""])
+            .arg(root().join("shunting_yard.rs")).arg("-o").arg(&library).output().unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(stderr(&output), "");
+    });
+
+    library
+}
+
+// Builds the source against the library, with `extra` arguments to rustc.
+fn build(name: &str, source: &str, extra: &[&str]) -> PathBuf {
+    let library = library();
+    let file = dir().join(format!("{}.rs", name));
+    fs::write(&file, source).unwrap();
+    let output = dir().join(name);
+    let built = rustc().args(["--edition", "2021"]).args(extra).arg("--extern").arg(format!("shunting_yard={}", library.display()))
+        .arg(&file).arg("-o").arg(&output).output().unwrap();
+    assert!(built.status.success(), "{}", stderr(&built));
+
+    output
+}
+
+#[test]
+fn builds_without_std() {
+    assert!(library().exists());
+}
+
+#[test]
+fn is_usable_from_a_crate_without_std() {
+    let source = "#![no_std]\n\
+        pub fn half(input: &str) -> Option<f64> {\n\
+            shunting_yard::evaluate_str(input).ok().map(|result| result / 2.0)\n\
+        }\n";
+    build("user", source, &["--crate-type", "lib"]);
+}
+
+// The float functions that std has and core has not are the library's own then, so they are checked here as well.
+#[test]
+fn evaluates_without_std() {
+    let source = r#"
+        fn main() {
+            let inputs = ["2 + 3 * 4", "sqrt(16) + 2 ^ 10", "floor(-2.5) + round(2.5) + trunc(-1.5)", "max(1, 5, 3) // 2",
+                "cos(0) + ln(1) + log10(1000)", "1 / 0", "(-8) ^ (1 / 3)", include_str!("EXPRESSION")];
+            for input in inputs.iter() {
+                match shunting_yard::evaluate_all(input).pop().unwrap().1 {
+                    Ok(result) => println!("{}", result),
+                    Err(error) => println!("{}", error),
+                }
+            }
+
+            let mut session = shunting_yard::Session::new();
+            session.evaluate("x = 3 + 2").unwrap();
+            println!("{}", session.evaluate("x * 4").unwrap());
+        }
+    "#;
+    let source = source.replace("EXPRESSION", &fs::canonicalize(root().join("expression.txt")).unwrap().display().to_string());
+    let output = Command::new(build("evaluate", &source, &[])).output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), [
+        "14", "1028", "-1", "2", "4", "Division by zero at line 1, column 3.", "^ does not give a number at line 1, column 6.", "-56", "20", "",
+    ].join("\n"));
+}